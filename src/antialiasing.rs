@@ -0,0 +1,51 @@
+use nalgebra_glm::Vec2;
+
+// Standard 4x and 8x sample patterns, in sixteenths of a pixel from its center
+const PATTERN_1X: [(i32, i32); 1] = [(0, 0)];
+const PATTERN_4X: [(i32, i32); 4] = [(-2, -6), (6, -2), (-6, 2), (2, 6)];
+const PATTERN_8X: [(i32, i32); 8] = [
+    (1, -3), (-1, 3), (5, 1), (-3, -5),
+    (-5, 5), (-7, -1), (3, 7), (7, -7),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AntiAliasing {
+    Off,
+    Msaa4,
+    Msaa8,
+    // Post-process edge smoothing on the presented image
+    Fxaa,
+}
+
+impl AntiAliasing {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AntiAliasing::Off => "OFF",
+            AntiAliasing::Msaa4 => "MSAA 4X",
+            AntiAliasing::Msaa8 => "MSAA 8X",
+            AntiAliasing::Fxaa => "FXAA",
+        }
+    }
+
+    pub fn next(&self) -> AntiAliasing {
+        match self {
+            AntiAliasing::Off => AntiAliasing::Msaa4,
+            AntiAliasing::Msaa4 => AntiAliasing::Msaa8,
+            AntiAliasing::Msaa8 => AntiAliasing::Fxaa,
+            AntiAliasing::Fxaa => AntiAliasing::Off,
+        }
+    }
+
+    // Sample positions inside the pixel, with (0, 0) the top-left corner
+    pub fn sample_positions(&self) -> Vec<Vec2> {
+        let pattern: &[(i32, i32)] = match self {
+            AntiAliasing::Msaa4 => &PATTERN_4X,
+            AntiAliasing::Msaa8 => &PATTERN_8X,
+            AntiAliasing::Off | AntiAliasing::Fxaa => &PATTERN_1X,
+        };
+
+        pattern.iter()
+            .map(|(x, y)| Vec2::new(0.5 + *x as f32 / 16.0, 0.5 + *y as f32 / 16.0))
+            .collect()
+    }
+}
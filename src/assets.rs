@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Directory searched by file name when a referenced file is not where it says
+const SEARCH_DIRECTORY: &str = "assets";
+// Keeps the file name search from wandering through huge or looping trees
+const MAX_SEARCH_DEPTH: usize = 8;
+
+// Finds files referenced from inside other assets, like the textures of an MTL
+// file. Exported models often keep the absolute paths of the artist's machine
+// (`/home/dennis/Downloads/.../textura nueva.jpg`, even Windows paths), so a
+// reference is tried in order:
+//   1. as written, relative to the file that references it
+//   2. relative to the configured asset root
+//   3. its file name next to the referencing file and in the asset root
+//   4. its file name anywhere under `assets/`
+pub struct AssetResolver {
+    root: Option<PathBuf>,
+    search_directory: PathBuf,
+}
+
+impl Default for AssetResolver {
+    fn default() -> Self {
+        AssetResolver::new(None)
+    }
+}
+
+impl AssetResolver {
+    pub fn new(root: Option<PathBuf>) -> Self {
+        AssetResolver { root, search_directory: PathBuf::from(SEARCH_DIRECTORY) }
+    }
+
+    // Existing file for `reference`, found from a file in `base_dir`
+    pub fn resolve(&self, reference: &str, base_dir: &Path) -> Option<PathBuf> {
+        // Paths written on Windows use backslashes
+        let reference = reference.trim().replace('\\', "/");
+        let path = Path::new(&reference);
+        let file_name = Path::new(path.file_name()?);
+
+        let mut candidates = vec![base_dir.join(path)];
+        if let Some(root) = &self.root {
+            // Joining an absolute path would replace the root
+            candidates.push(root.join(path.strip_prefix("/").unwrap_or(path)));
+        }
+        candidates.push(base_dir.join(file_name));
+        if let Some(root) = &self.root {
+            candidates.push(root.join(file_name));
+        }
+
+        candidates.into_iter()
+            .find(|candidate| candidate.is_file())
+            .or_else(|| find_file(&self.search_directory, file_name, MAX_SEARCH_DEPTH))
+    }
+}
+
+// First file named `file_name` under `directory`, ignoring case, looking
+// through files before subdirectories and in sorted order
+fn find_file(directory: &Path, file_name: &Path, depth: usize) -> Option<PathBuf> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(directory).ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+
+    let name = file_name.to_string_lossy();
+    let found = entries.iter()
+        .filter(|path| path.is_file())
+        .find(|path| path.file_name().is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(&name)));
+    if found.is_some() || depth == 0 {
+        return found.cloned();
+    }
+
+    entries.iter()
+        .filter(|path| path.is_dir())
+        .find_map(|path| find_file(path, file_name, depth - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fresh directory under the system temp directory, deleted with
+    // everything in it when dropped, even if the test fails
+    struct ScratchDirectory {
+        path: PathBuf,
+    }
+
+    impl Drop for ScratchDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    // Scratch directory holding empty `files`
+    fn scratch_directory(name: &str, files: &[&str]) -> ScratchDirectory {
+        let path = std::env::temp_dir().join(format!("assets-test-{}-{}", name, std::process::id()));
+        let directory = ScratchDirectory { path };
+        let _ = fs::remove_dir_all(&directory.path);
+        for file in files {
+            let path = directory.path.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn relative_references_resolve_next_to_the_referencing_file() {
+        let scratch = scratch_directory("relative", &["models/textures/skin.png"]);
+        let directory = &scratch.path;
+        let resolver = AssetResolver::new(None);
+
+        let found = resolver.resolve("textures/skin.png", &directory.join("models"));
+        assert_eq!(found, Some(directory.join("models/textures/skin.png")));
+    }
+
+    #[test]
+    fn foreign_absolute_paths_fall_back_to_the_file_name() {
+        let scratch = scratch_directory("foreign", &["root/maps/TEX_head.png", "models/skin.png"]);
+        let directory = &scratch.path;
+        let resolver = AssetResolver {
+            root: Some(directory.join("root")),
+            search_directory: directory.clone(),
+        };
+        let models = directory.join("models");
+
+        // Next to the referencing file
+        let found = resolver.resolve("/home/dennis/Downloads/source/skin.png", &models);
+        assert_eq!(found, Some(models.join("skin.png")));
+        // Anywhere under the search directory, written as a Windows path
+        let found = resolver.resolve("D:\\Art\\maps\\TEX_head.png", &models);
+        assert_eq!(found, Some(directory.join("root/maps/TEX_head.png")));
+        // Nowhere
+        assert_eq!(resolver.resolve("/home/dennis/missing.png", &models), None);
+    }
+}
@@ -0,0 +1,173 @@
+use nalgebra_glm::{Vec3, dot};
+use crate::framebuffer::Framebuffer;
+
+const LUMINANCE: Vec3 = Vec3::new(0.2126, 0.7152, 0.0722);
+const BLUR_RADIUS: i32 = 4;
+const BLUR_SIGMA: f32 = 2.0;
+
+pub struct BloomSettings {
+    pub enabled: bool,
+    // Luminance above which pixels start to glow
+    pub threshold: f32,
+    pub intensity: f32,
+    // Number of mip levels, each half the size of the previous one
+    pub levels: usize,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        BloomSettings {
+            enabled: true,
+            threshold: 1.0,
+            intensity: 0.8,
+            levels: 5,
+        }
+    }
+}
+
+struct Level {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl Level {
+    fn get(&self, x: i32, y: i32) -> Vec3 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    // Bilinear lookup with u, v in 0.0 - 1.0 over the whole level
+    fn sample(&self, u: f32, v: f32) -> Vec3 {
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self.get(x0, y0) * (1.0 - tx) + self.get(x0 + 1, y0) * tx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - tx) + self.get(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    // 2x2 box filter down to half resolution
+    fn downsample(&self) -> Level {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let sum = self.get(x * 2, y * 2)
+                    + self.get(x * 2 + 1, y * 2)
+                    + self.get(x * 2, y * 2 + 1)
+                    + self.get(x * 2 + 1, y * 2 + 1);
+                pixels.push(sum * 0.25);
+            }
+        }
+
+        Level { width, height, pixels }
+    }
+
+    // Separable Gaussian blur: one horizontal pass, then one vertical pass
+    fn blur(&mut self, kernel: &[f32]) {
+        let mut horizontal = Vec::with_capacity(self.pixels.len());
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let mut sum = Vec3::zeros();
+                for (i, weight) in kernel.iter().enumerate() {
+                    sum += self.get(x + i as i32 - BLUR_RADIUS, y) * *weight;
+                }
+                horizontal.push(sum);
+            }
+        }
+        self.pixels = horizontal;
+
+        let mut vertical = Vec::with_capacity(self.pixels.len());
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let mut sum = Vec3::zeros();
+                for (i, weight) in kernel.iter().enumerate() {
+                    sum += self.get(x, y + i as i32 - BLUR_RADIUS) * *weight;
+                }
+                vertical.push(sum);
+            }
+        }
+        self.pixels = vertical;
+    }
+}
+
+fn gaussian_kernel() -> Vec<f32> {
+    let weights: Vec<f32> = (-BLUR_RADIUS..=BLUR_RADIUS)
+        .map(|i| (-(i * i) as f32 / (2.0 * BLUR_SIGMA * BLUR_SIGMA)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+// Keeps only the part of each pixel brighter than the threshold
+fn bright_pass(color: Vec3, threshold: f32) -> Vec3 {
+    let luminance = dot(&color, &LUMINANCE);
+    if luminance <= threshold {
+        return Vec3::zeros();
+    }
+    color * ((luminance - threshold) / luminance)
+}
+
+// Adds a blurred halo around everything brighter than the threshold to the hdr buffer
+pub fn apply_bloom(framebuffer: &mut Framebuffer, settings: &BloomSettings) {
+    if !settings.enabled || settings.levels == 0 {
+        return;
+    }
+
+    // Bright pass straight into the first half-resolution level
+    let width = (framebuffer.width / 2).max(1);
+    let height = (framebuffer.height / 2).max(1);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = Vec3::zeros();
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let sx = (x * 2 + dx).min(framebuffer.width - 1);
+                let sy = (y * 2 + dy).min(framebuffer.height - 1);
+                sum += bright_pass(framebuffer.hdr[sy * framebuffer.width + sx], settings.threshold);
+            }
+            pixels.push(sum * 0.25);
+        }
+    }
+
+    let kernel = gaussian_kernel();
+    let mut levels: Vec<Level> = Vec::with_capacity(settings.levels);
+    let mut source = Level { width, height, pixels };
+    for _ in 0..settings.levels {
+        let next = source.downsample();
+        source.blur(&kernel);
+        levels.push(source);
+        source = next;
+    }
+
+    // Fold the chain from the smallest level up, so the full-resolution
+    // composite only has to read the largest level once per pixel
+    while levels.len() > 1 {
+        let smaller = levels.pop().unwrap();
+        let larger = levels.last_mut().unwrap();
+        for y in 0..larger.height {
+            let v = (y as f32 + 0.5) / larger.height as f32;
+            for x in 0..larger.width {
+                let u = (x as f32 + 0.5) / larger.width as f32;
+                larger.pixels[y * larger.width + x] += smaller.sample(u, v);
+            }
+        }
+    }
+
+    let glow = &levels[0];
+    let weight = settings.intensity / settings.levels as f32;
+    for y in 0..framebuffer.height {
+        let v = (y as f32 + 0.5) / framebuffer.height as f32;
+        for x in 0..framebuffer.width {
+            let u = (x as f32 + 0.5) / framebuffer.width as f32;
+            framebuffer.hdr[y * framebuffer.width + x] += glow.sample(u, v) * weight;
+        }
+    }
+}
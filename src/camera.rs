@@ -0,0 +1,105 @@
+use nalgebra_glm::{Vec3, Mat3, Mat4};
+
+// Orbit camera for the screen-space scene: the world is rotated around `center`
+// (the middle of the window) by yaw and pitch. The field of view is only used
+// for things drawn at infinite distance, like the background.
+pub struct Camera {
+    pub center: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+}
+
+const MAX_PITCH: f32 = 1.5;
+
+impl Camera {
+    pub fn new(center: Vec3) -> Self {
+        Camera {
+            center,
+            yaw: 0.0,
+            pitch: 0.0,
+            fov: 60.0_f32.to_radians(),
+        }
+    }
+
+    pub fn orbit(&mut self, delta_yaw: f32, delta_pitch: f32) {
+        self.yaw += delta_yaw;
+        self.pitch = (self.pitch + delta_pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    // World to view rotation
+    pub fn rotation(&self) -> Mat3 {
+        let (sin_y, cos_y) = self.yaw.sin_cos();
+        let (sin_x, cos_x) = self.pitch.sin_cos();
+
+        let rotation_y = Mat3::new(
+            cos_y,  0.0, sin_y,
+            0.0,    1.0, 0.0,
+            -sin_y, 0.0, cos_y,
+        );
+
+        let rotation_x = Mat3::new(
+            1.0, 0.0,    0.0,
+            0.0, cos_x, -sin_x,
+            0.0, sin_x,  cos_x,
+        );
+
+        rotation_x * rotation_y
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        let r = self.rotation();
+        let c = self.center;
+        let offset = c - r * c;
+
+        Mat4::new(
+            r[(0, 0)], r[(0, 1)], r[(0, 2)], offset.x,
+            r[(1, 0)], r[(1, 1)], r[(1, 2)], offset.y,
+            r[(2, 0)], r[(2, 1)], r[(2, 2)], offset.z,
+            0.0,       0.0,       0.0,       1.0,
+        )
+    }
+
+    // Distance in pixels from the eye to the image plane for the current fov
+    pub fn focal_length(&self, height: usize) -> f32 {
+        (height as f32 * 0.5) / (self.fov * 0.5).tan()
+    }
+
+    // Precomputes what is needed to build many pixel rays for one frame
+    pub fn rays(&self, width: usize, height: usize) -> RayGenerator {
+        RayGenerator {
+            inverse_rotation: self.rotation().transpose(),
+            focal: self.focal_length(height),
+            half_width: width as f32 * 0.5,
+            half_height: height as f32 * 0.5,
+        }
+    }
+
+    // Screen position of a world-space direction, or None if it is behind the camera
+    pub fn project_direction(&self, direction: &Vec3, width: usize, height: usize) -> Option<(f32, f32)> {
+        let view_dir = self.rotation() * direction;
+        if view_dir.z <= 0.0 {
+            return None;
+        }
+
+        let focal = self.focal_length(height);
+        Some((
+            width as f32 * 0.5 + focal * view_dir.x / view_dir.z,
+            height as f32 * 0.5 + focal * view_dir.y / view_dir.z,
+        ))
+    }
+}
+
+pub struct RayGenerator {
+    inverse_rotation: Mat3,
+    focal: f32,
+    half_width: f32,
+    half_height: f32,
+}
+
+impl RayGenerator {
+    pub fn direction(&self, x: f32, y: f32) -> Vec3 {
+        let view_dir = Vec3::new(x - self.half_width, y - self.half_height, self.focal).normalize();
+        self.inverse_rotation * view_dir
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt;
+use nalgebra_glm::Vec3;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
@@ -8,11 +9,6 @@ pub struct Color {
 }
 
 impl Color {
-  // Constructor to initialize the color using r, g, b values as u8
-  pub fn new(r: u8, g: u8, b: u8) -> Self {
-    Color { r, g, b }
-  }
-
   // default color
   pub fn black() -> Self {
     Color { r: 0, g: 0, b: 0 }
@@ -35,12 +31,45 @@ impl Color {
     Color { r, g, b }
   }
 
+  // Function to return the color as 0.0 - 1.0 floats
+  pub fn to_vec3(self) -> Vec3 {
+    Vec3::new(self.r as f32, self.g as f32, self.b as f32) / 255.0
+  }
+
   // Function to return the color as a hex value
-  pub fn to_hex(&self) -> u32 {
+  pub fn to_hex(self) -> u32 {
     ((self.r as u32) << 16) | ((self.g as u32) << 8) | (self.b as u32)
   }
 }
 
+// sRGB transfer function, applied per channel. Values above 1.0 are extended
+// along the same curve so HDR colors keep their ratio.
+fn srgb_channel_to_linear(c: f32) -> f32 {
+  let c = c.max(0.0);
+  if c <= 0.04045 {
+    c / 12.92
+  } else {
+    ((c + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+fn linear_channel_to_srgb(c: f32) -> f32 {
+  let c = c.max(0.0);
+  if c <= 0.0031308 {
+    c * 12.92
+  } else {
+    1.055 * c.powf(1.0 / 2.4) - 0.055
+  }
+}
+
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+  color.map(srgb_channel_to_linear)
+}
+
+pub fn linear_to_srgb(color: Vec3) -> Vec3 {
+  color.map(linear_channel_to_srgb)
+}
+
 // Implement addition for Color
 use std::ops::Add;
 
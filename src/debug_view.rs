@@ -0,0 +1,126 @@
+use nalgebra_glm::Vec3;
+use crate::color::srgb_to_linear;
+use crate::fragment_shaders::{FragmentShader, ShaderInput};
+use crate::framebuffer::Framebuffer;
+use crate::line::LineStyle;
+use crate::Uniforms;
+
+pub const WIREFRAME_COLOR: Vec3 = Vec3::new(0.1, 1.0, 0.3);
+// Pulls wireframe edges towards the camera so they win the depth test
+// against the surface they lie on, but not against surfaces in front of it
+pub const WIREFRAME_DEPTH_BIAS: f32 = 0.5;
+pub const WIREFRAME_STYLE: LineStyle = LineStyle { thickness: 1.0, antialiased: true };
+
+// Fragment counts at or above this show as white in the overdraw view
+const OVERDRAW_MAX: f32 = 6.0;
+const HEAT_COLORS: [Vec3; 5] = [
+    Vec3::new(0.0, 0.0, 0.5),
+    Vec3::new(0.0, 0.6, 1.0),
+    Vec3::new(0.2, 1.0, 0.2),
+    Vec3::new(1.0, 0.8, 0.0),
+    Vec3::new(1.0, 0.1, 0.0),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DebugView {
+    Shaded,
+    // Shaded scene with depth tested triangle edges on top
+    Wireframe,
+    Normals,
+    Depth,
+    TexCoords,
+    // Number of fragments generated for each pixel, before the depth test
+    Overdraw,
+}
+
+impl DebugView {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebugView::Shaded => "SHADED",
+            DebugView::Wireframe => "WIREFRAME",
+            DebugView::Normals => "NORMALS",
+            DebugView::Depth => "DEPTH",
+            DebugView::TexCoords => "UV",
+            DebugView::Overdraw => "OVERDRAW",
+        }
+    }
+
+    pub fn next(&self) -> DebugView {
+        match self {
+            DebugView::Shaded => DebugView::Wireframe,
+            DebugView::Wireframe => DebugView::Normals,
+            DebugView::Normals => DebugView::Depth,
+            DebugView::Depth => DebugView::TexCoords,
+            DebugView::TexCoords => DebugView::Overdraw,
+            DebugView::Overdraw => DebugView::Shaded,
+        }
+    }
+
+    // Views that show the lit scene; the rest show raw data and skip
+    // emission, bloom and tone mapping
+    pub fn is_shaded(&self) -> bool {
+        matches!(self, DebugView::Shaded | DebugView::Wireframe)
+    }
+
+    // Shader used instead of the body's own one
+    pub fn fragment_shader(&self) -> Option<FragmentShader> {
+        match self {
+            DebugView::Normals => Some(normal_shader),
+            DebugView::TexCoords => Some(tex_coords_shader),
+            _ => None,
+        }
+    }
+
+    // Replaces the resolved scene with the data this view shows
+    pub fn apply(&self, framebuffer: &mut Framebuffer) {
+        match self {
+            DebugView::Depth => show_depth(framebuffer),
+            DebugView::Overdraw => show_overdraw(framebuffer),
+            _ => {}
+        }
+    }
+}
+
+// Debug colors are written so they come out unchanged on screen after the
+// linear to sRGB conversion in `present`
+fn normal_shader(input: &ShaderInput, _uniforms: &Uniforms) -> Vec3 {
+    srgb_to_linear(input.normal * 0.5 + Vec3::repeat(0.5))
+}
+
+fn tex_coords_shader(input: &ShaderInput, _uniforms: &Uniforms) -> Vec3 {
+    srgb_to_linear(Vec3::new(input.tex_coords.x.fract(), input.tex_coords.y.fract(), 0.0))
+}
+
+// Grayscale depth, near surfaces white, normalized to the range drawn this frame
+fn show_depth(framebuffer: &mut Framebuffer) {
+    let (near, far) = framebuffer.zbuffer.iter()
+        .filter(|depth| depth.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(near, far), depth| (near.min(*depth), far.max(*depth)));
+    let range = (far - near).max(f32::EPSILON);
+
+    for (pixel, depth) in framebuffer.hdr.iter_mut().zip(framebuffer.zbuffer.iter()) {
+        *pixel = if depth.is_finite() {
+            srgb_to_linear(Vec3::repeat(1.0 - 0.9 * (depth - near) / range))
+        } else {
+            Vec3::zeros()
+        };
+    }
+}
+
+// The scene is drawn with every fragment adding 1.0 to its pixel, so the
+// red channel holds the count to turn into a heat map
+fn show_overdraw(framebuffer: &mut Framebuffer) {
+    for pixel in framebuffer.hdr.iter_mut() {
+        let count = pixel.x;
+        *pixel = if count <= 0.0 {
+            Vec3::zeros()
+        } else if count >= OVERDRAW_MAX {
+            Vec3::repeat(1.0)
+        } else {
+            let t = (count - 1.0) / (OVERDRAW_MAX - 1.0) * (HEAT_COLORS.len() - 1) as f32;
+            let i = (t as usize).min(HEAT_COLORS.len() - 2);
+            let f = t - i as f32;
+            srgb_to_linear(HEAT_COLORS[i] * (1.0 - f) + HEAT_COLORS[i + 1] * f)
+        };
+    }
+}
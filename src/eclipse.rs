@@ -0,0 +1,122 @@
+use nalgebra_glm::{Mat4, Vec3, Vec4};
+use std::f32::consts::PI;
+use crate::shadow::ShadowLight;
+
+// Points this close to an occluder's surface, as a fraction of its radius,
+// belong to it. Sphere meshes lie on or inside their sphere.
+const SURFACE_TOLERANCE: f32 = 1e-3;
+
+// A sphere blocking the light, in world space
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Occluder {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Occluder {
+    // Sphere of `radius` around the origin of a mesh placed with
+    // `model_matrix`, which only rotates, scales uniformly and translates
+    pub fn from_model(model_matrix: &Mat4, radius: f32) -> Self {
+        let center = model_matrix * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let axis = model_matrix * Vec4::new(1.0, 0.0, 0.0, 0.0);
+        Occluder { center: center.xyz(), radius: axis.xyz().norm() * radius }
+    }
+}
+
+// The light's disk and the spheres that can cover it, for soft shadows worked
+// out per fragment instead of from a shadow map
+pub struct Eclipses {
+    light: ShadowLight,
+    // The star's radius for a point light, or the angle in radians the disk
+    // spans from its center to its edge for a directional one
+    light_size: f32,
+    occluders: Vec<Occluder>,
+}
+
+impl Eclipses {
+    pub fn new(light: ShadowLight, light_size: f32, occluders: Vec<Occluder>) -> Self {
+        Eclipses { light, light_size, occluders }
+    }
+
+    // Fraction of the light's disk, as seen from `point`, left uncovered by
+    // the occluders. Occluders the point is on are skipped, and the shadows
+    // of several are taken as independent. The light's own center sees all
+    // of it.
+    pub fn visibility(&self, point: Vec3) -> f32 {
+        let (towards_light, light_distance, light_radius) = match self.light {
+            ShadowLight::Point(center) => {
+                let offset = center - point;
+                let distance = offset.norm();
+                if distance == 0.0 {
+                    return 1.0;
+                }
+                (offset / distance, distance, (self.light_size / distance).min(1.0).asin())
+            }
+            ShadowLight::Directional(direction) => (direction.normalize(), f32::INFINITY, self.light_size),
+        };
+        let light_area = PI * light_radius * light_radius;
+
+        let mut visibility = 1.0;
+        for occluder in &self.occluders {
+            let offset = occluder.center - point;
+            let distance = offset.norm();
+            if distance <= occluder.radius * (1.0 + SURFACE_TOLERANCE) || distance - occluder.radius >= light_distance {
+                continue;
+            }
+            // Both disks are measured in angles across the sky around the point
+            let radius = (occluder.radius / distance).asin();
+            let separation = (offset / distance).dot(&towards_light).clamp(-1.0, 1.0).acos();
+            visibility *= 1.0 - disk_overlap(light_radius, radius, separation) / light_area;
+        }
+        visibility.clamp(0.0, 1.0)
+    }
+}
+
+// Area shared by two disks of radii `a` and `b` with centers `distance` apart
+fn disk_overlap(a: f32, b: f32, distance: f32) -> f32 {
+    if distance >= a + b {
+        return 0.0;
+    }
+    if distance <= (a - b).abs() {
+        let smaller = a.min(b);
+        return PI * smaller * smaller;
+    }
+    // Two circular segments, each a sector minus its triangle, with the
+    // triangles making up the kite between the centers and the crossings
+    let half_angle_a = ((distance * distance + a * a - b * b) / (2.0 * distance * a)).clamp(-1.0, 1.0).acos();
+    let half_angle_b = ((distance * distance + b * b - a * a) / (2.0 * distance * b)).clamp(-1.0, 1.0).acos();
+    let kite = 0.5 * ((-distance + a + b) * (distance + a - b) * (distance - a + b) * (distance + a + b)).max(0.0).sqrt();
+    a * a * half_angle_a + b * b * half_angle_b - kite
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moon_between_star_and_planet_gives_umbra_penumbra_and_light() {
+        // From x = 200 the star and the moon span the same angle in the sky
+        let moon = Occluder { center: Vec3::new(100.0, 0.0, 0.0), radius: 5.0 };
+        let eclipses = Eclipses::new(ShadowLight::Point(Vec3::zeros()), 10.0, vec![moon]);
+
+        assert!(eclipses.visibility(Vec3::new(200.0, 0.0, 0.0)) < 1e-3);
+        let penumbra = eclipses.visibility(Vec3::new(200.0, 10.0, 0.0));
+        assert!(penumbra > 0.1 && penumbra < 0.9);
+        assert_eq!(eclipses.visibility(Vec3::new(200.0, 25.0, 0.0)), 1.0);
+
+        // The moon doesn't shadow itself, nor points between it and the star
+        assert_eq!(eclipses.visibility(Vec3::new(95.0, 0.0, 0.0)), 1.0);
+        assert_eq!(eclipses.visibility(Vec3::new(50.0, 0.0, 0.0)), 1.0);
+        assert_eq!(eclipses.visibility(Vec3::zeros()), 1.0);
+    }
+
+    #[test]
+    fn occluder_smaller_than_the_sun_leaves_a_ring_of_light() {
+        let occluder = Occluder { center: Vec3::new(0.0, 0.0, -100.0), radius: 1.0 };
+        let eclipses = Eclipses::new(ShadowLight::Directional(Vec3::new(0.0, 0.0, -1.0)), 0.02, vec![occluder]);
+
+        // Centered, the occluder covers (0.01 / 0.02)^2 of the sun's disk
+        let annular = eclipses.visibility(Vec3::zeros());
+        assert!((annular - 0.75).abs() < 1e-2);
+    }
+}
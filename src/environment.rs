@@ -0,0 +1,124 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+use std::io::Result;
+use std::path::Path;
+
+use crate::camera::Camera;
+use crate::color::{Color, srgb_to_linear};
+use crate::framebuffer::Framebuffer;
+use crate::image::Image;
+use crate::starfield::Starfield;
+
+// Face order: +X, -X, +Y, -Y, +Z, -Z
+const CUBE_FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+const IMAGE_EXTENSIONS: [&str; 2] = ["ppm", "tga"];
+
+pub struct CubeMap {
+    faces: Vec<Image>,
+}
+
+impl CubeMap {
+    // Loads px/nx/py/ny/pz/nz images (.ppm or .tga) from a directory
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Self> {
+        let directory = directory.as_ref();
+        let mut faces = Vec::with_capacity(6);
+
+        for name in CUBE_FACE_NAMES {
+            let path = IMAGE_EXTENSIONS.iter()
+                .map(|extension| directory.join(format!("{}.{}", name, extension)))
+                .find(|path| path.exists())
+                .unwrap_or_else(|| directory.join(format!("{}.ppm", name)));
+            faces.push(Image::load(path)?.into_linear());
+        }
+
+        Ok(CubeMap { faces })
+    }
+
+    // Uses the usual OpenGL face layout, which is Y-up and looks down -Z, so the
+    // direction is flipped from the screen-space convention (Y-down, +Z forward)
+    pub fn sample(&self, direction: &Vec3) -> Vec3 {
+        let (x, y, z) = (direction.x, -direction.y, -direction.z);
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+        let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+            if x > 0.0 { (0, -z, -y, ax) } else { (1, z, -y, ax) }
+        } else if ay >= az {
+            if y > 0.0 { (2, x, z, ay) } else { (3, x, -z, ay) }
+        } else if z > 0.0 {
+            (4, x, -y, az)
+        } else {
+            (5, -x, -y, az)
+        };
+
+        let u = (sc / ma + 1.0) * 0.5;
+        let v = (tc / ma + 1.0) * 0.5;
+        let image = &self.faces[face];
+        let px = ((u * image.width as f32) as usize).min(image.width - 1);
+        let py = ((v * image.height as f32) as usize).min(image.height - 1);
+        image.pixel(px, py)
+    }
+}
+
+// Equirectangular panorama: longitude along u, latitude along v (top row is straight up)
+pub struct Panorama {
+    image: Image,
+}
+
+impl Panorama {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Panorama { image: Image::load(path)?.into_linear() })
+    }
+
+    pub fn sample(&self, direction: &Vec3) -> Vec3 {
+        let up = (-direction.y).clamp(-1.0, 1.0);
+        let u = 0.5 + direction.x.atan2(direction.z) / (2.0 * PI);
+        let v = up.acos() / PI;
+        self.image.sample_bilinear(u, v)
+    }
+}
+
+pub enum Environment {
+    SolidColor(u32),
+    Starfield(Starfield),
+    CubeMap(CubeMap),
+    Panorama(Panorama),
+}
+
+impl Environment {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Environment::SolidColor(_) => "SOLID",
+            Environment::Starfield(_) => "STARFIELD",
+            Environment::CubeMap(_) => "SKYBOX",
+            Environment::Panorama(_) => "PANORAMA",
+        }
+    }
+
+    // Clears the framebuffer to this environment as seen by the camera
+    pub fn render(&self, framebuffer: &mut Framebuffer, camera: &Camera) {
+        let rays = camera.rays(framebuffer.width, framebuffer.height);
+
+        match self {
+            Environment::SolidColor(color) => {
+                let color = srgb_to_linear(Color::from_hex(*color).to_vec3());
+                framebuffer.clear_with(|_, _| color);
+            }
+            Environment::Starfield(starfield) => {
+                framebuffer.clear();
+                starfield.render(framebuffer, camera);
+            }
+            Environment::CubeMap(cube_map) => {
+                framebuffer.clear_with(|x, y| {
+                    let direction = rays.direction(x as f32 + 0.5, y as f32 + 0.5);
+                    cube_map.sample(&direction)
+                });
+            }
+            Environment::Panorama(panorama) => {
+                framebuffer.clear_with(|x, y| {
+                    let direction = rays.direction(x as f32 + 0.5, y as f32 + 0.5);
+                    panorama.sample(&direction)
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use nalgebra_glm::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use crate::color::linear_to_srgb;
+use crate::fragment_shaders::{FragmentShader, ShaderInput};
+use crate::shaders::vertex_shader;
+use crate::vertex::Vertex;
+use crate::Uniforms;
+
+// One mesh of an exported scene: a triangle list like the ones given to
+// `render`, placed with `model_matrix`
+pub struct ExportMesh<'a> {
+    pub name: &'a str,
+    pub vertices: &'a [Vertex],
+    pub model_matrix: Mat4,
+    // Linear color per vertex from `bake_colors`, only written to PLY files.
+    // Vertices are white when empty.
+    pub colors: Vec<Vec3>,
+}
+
+// Runs the fragment shader once per vertex, as if a pixel landed exactly on
+// it, so the shading can be stored as vertex colors. Lighting depends on the
+// view, so the colors match the scene as seen through `uniforms.view_matrix`.
+// Shadows from the frame's `shadow_map` or `eclipses` are baked in too; clear
+// them from the uniforms for colors without shadows.
+pub fn bake_colors(vertices: &[Vertex], fragment_shader: FragmentShader, uniforms: &Uniforms) -> Vec<Vec3> {
+    vertices.iter().map(|vertex| {
+        let transformed = vertex_shader(vertex, uniforms);
+        let input = ShaderInput {
+            position: vertex.position,
+            normal: transformed.transformed_normal.normalize(),
+            tex_coords: vertex.tex_coords,
+            // No pixel footprint: procedural detail is kept at full strength
+            dpos_dx: Vec3::zeros(),
+            dpos_dy: Vec3::zeros(),
+            duv_dx: Vec2::zeros(),
+            duv_dy: Vec2::zeros(),
+        };
+        fragment_shader(&input, uniforms) * uniforms.emission
+    }).collect()
+}
+
+// Writes `<directory>/<name>.obj` and `<directory>/<name>.ply`, returning their paths
+pub fn export_scene(directory: &Path, name: &str, meshes: &[ExportMesh]) -> Result<(PathBuf, PathBuf)> {
+    fs::create_dir_all(directory)?;
+    let obj_path = directory.join(format!("{}.obj", name));
+    let ply_path = directory.join(format!("{}.ply", name));
+
+    let mut obj = BufWriter::new(File::create(&obj_path)?);
+    write_obj(&mut obj, meshes)?;
+    obj.flush()?;
+    let mut ply = BufWriter::new(File::create(&ply_path)?);
+    write_ply(&mut ply, meshes)?;
+    ply.flush()?;
+
+    Ok((obj_path, ply_path))
+}
+
+// Wavefront OBJ with one object per mesh, positions, normals and texture
+// coordinates. Axes are turned back into the OBJ convention `Obj::load`
+// reads by default (y up, v up), so exported files load back unchanged.
+pub fn write_obj<W: Write>(out: &mut W, meshes: &[ExportMesh]) -> Result<()> {
+    writeln!(out, "# Exported from the software renderer")?;
+
+    // Attributes are shared across the whole file, so indices keep counting
+    let mut positions = Indexer::default();
+    let mut normals = Indexer::default();
+    let mut tex_coords = Indexer::default();
+
+    for mesh in meshes {
+        writeln!(out, "o {}", mesh.name.replace(char::is_whitespace, "_"))?;
+        let placed = place(mesh);
+
+        let mut corners = Vec::with_capacity(placed.len());
+        for vertex in &placed {
+            let (p, n, t) = (vertex.position, vertex.normal, vertex.tex_coords);
+            let (position, new) = positions.index(&[p.x, p.y, p.z]);
+            if new {
+                writeln!(out, "v {} {} {}", p.x, -p.y, -p.z)?;
+            }
+            let (normal, new) = normals.index(&[n.x, n.y, n.z]);
+            if new {
+                writeln!(out, "vn {} {} {}", n.x, -n.y, -n.z)?;
+            }
+            let (tex_coord, new) = tex_coords.index(&[t.x, t.y]);
+            if new {
+                writeln!(out, "vt {} {}", t.x, 1.0 - t.y)?;
+            }
+            corners.push(format!("{}/{}/{}", position + 1, tex_coord + 1, normal + 1));
+        }
+
+        for face in corners.chunks_exact(3) {
+            writeln!(out, "f {} {} {}", face[0], face[1], face[2])?;
+        }
+    }
+
+    Ok(())
+}
+
+// ASCII PLY with positions, normals, texture coordinates and 8-bit sRGB
+// vertex colors, in the same axes as `write_obj`
+pub fn write_ply<W: Write>(out: &mut W, meshes: &[ExportMesh]) -> Result<()> {
+    // Vertices with every attribute equal are written once
+    let mut welded = Indexer::default();
+    let mut vertices: Vec<(Vertex, [u8; 3])> = Vec::new();
+    let mut faces: Vec<u32> = Vec::new();
+
+    for mesh in meshes {
+        for (i, vertex) in place(mesh).into_iter().enumerate() {
+            let color = mesh.colors.get(i).map_or([255; 3], |&color| to_rgb8(color));
+            let (p, n, t) = (vertex.position, vertex.normal, vertex.tex_coords);
+            let key = [p.x, p.y, p.z, n.x, n.y, n.z, t.x, t.y, color[0] as f32, color[1] as f32, color[2] as f32];
+            let (index, new) = welded.index(&key);
+            if new {
+                vertices.push((vertex, color));
+            }
+            faces.push(index as u32);
+        }
+    }
+
+    writeln!(out, "ply")?;
+    writeln!(out, "format ascii 1.0")?;
+    writeln!(out, "comment Exported from the software renderer")?;
+    writeln!(out, "element vertex {}", vertices.len())?;
+    for property in ["x", "y", "z", "nx", "ny", "nz", "s", "t"] {
+        writeln!(out, "property float {}", property)?;
+    }
+    for property in ["red", "green", "blue"] {
+        writeln!(out, "property uchar {}", property)?;
+    }
+    writeln!(out, "element face {}", faces.len() / 3)?;
+    writeln!(out, "property list uchar uint vertex_indices")?;
+    writeln!(out, "end_header")?;
+
+    for (vertex, [r, g, b]) in &vertices {
+        let (p, n, t) = (vertex.position, vertex.normal, vertex.tex_coords);
+        writeln!(out, "{} {} {} {} {} {} {} {} {} {} {}", p.x, -p.y, -p.z, n.x, -n.y, -n.z, t.x, 1.0 - t.y, r, g, b)?;
+    }
+    for face in faces.chunks_exact(3) {
+        writeln!(out, "3 {} {} {}", face[0], face[1], face[2])?;
+    }
+
+    Ok(())
+}
+
+// The mesh's vertices with the model matrix applied to positions and normals,
+// dropping a trailing partial triangle like `render` does
+fn place(mesh: &ExportMesh) -> Vec<Vertex> {
+    let m = &mesh.model_matrix;
+    let linear = Mat3::new(
+        m[(0, 0)], m[(0, 1)], m[(0, 2)],
+        m[(1, 0)], m[(1, 1)], m[(1, 2)],
+        m[(2, 0)], m[(2, 1)], m[(2, 2)],
+    );
+    let normal_matrix = linear.try_inverse().unwrap_or(Mat3::identity()).transpose();
+    let count = mesh.vertices.len() / 3 * 3;
+
+    mesh.vertices[..count].iter().map(|vertex| {
+        let p = m * Vec4::new(vertex.position.x, vertex.position.y, vertex.position.z, 1.0);
+        let normal = normal_matrix * vertex.normal;
+        let normal = if normal.norm() > 0.0 { normal.normalize() } else { normal };
+        Vertex::new(Vec3::new(p.x, p.y, p.z) / p.w, normal, vertex.tex_coords)
+    }).collect()
+}
+
+fn to_rgb8(color: Vec3) -> [u8; 3] {
+    let srgb = linear_to_srgb(color.map(|c| c.clamp(0.0, 1.0)));
+    [srgb.x, srgb.y, srgb.z].map(|c| (c * 255.0).round() as u8)
+}
+
+// Hands out indices to distinct attribute values, compared bit for bit
+#[derive(Default)]
+struct Indexer {
+    indices: HashMap<Vec<u32>, usize>,
+}
+
+impl Indexer {
+    // Index of `value` and whether it was seen for the first time
+    fn index(&mut self, value: &[f32]) -> (usize, bool) {
+        let next = self.indices.len();
+        let key = value.iter().map(|v| (v + 0.0).to_bits()).collect();
+        let index = *self.indices.entry(key).or_insert(next);
+        (index, index == next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::AssetResolver;
+    use crate::obj::{LoadOptions, Obj};
+    use crate::sphere::generate_sphere;
+
+    #[test]
+    fn exported_obj_loads_back_unchanged() {
+        let sphere = generate_sphere(1.0, 8);
+        let mesh = ExportMesh { name: "sphere", vertices: &sphere, model_matrix: Mat4::identity(), colors: Vec::new() };
+        let path = std::env::temp_dir().join(format!("export-test-{}.obj", std::process::id()));
+        write_obj(&mut File::create(&path).unwrap(), &[mesh]).unwrap();
+
+        let loaded = Obj::load(&path, &AssetResolver::default(), &LoadOptions::default()).unwrap()
+            .get_mesh_vertex_arrays().into_iter().flat_map(|(vertices, _)| vertices).collect::<Vec<_>>();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), sphere.len());
+        for (a, b) in sphere.iter().zip(&loaded) {
+            assert!((a.position - b.position).norm() < 1e-5);
+            assert!((a.normal - b.normal).norm() < 1e-5);
+            assert!((a.tex_coords - b.tex_coords).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn ply_welds_shared_vertices_and_writes_colors() {
+        let quad: Vec<Vertex> = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+            .iter()
+            .map(|&(x, y)| Vertex::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, -1.0), Vec2::new(x, y)))
+            .collect();
+        let mesh = ExportMesh {
+            name: "quad",
+            vertices: &quad,
+            model_matrix: Mat4::identity(),
+            colors: vec![Vec3::new(1.0, 0.0, 0.0); quad.len()],
+        };
+
+        let mut out = Vec::new();
+        write_ply(&mut out, &[mesh]).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.contains("element vertex 4\n"));
+        assert!(text.contains("element face 2\n"));
+        assert!(text.contains(" 255 0 0\n"));
+    }
+}
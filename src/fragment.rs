@@ -1,18 +1,51 @@
-use nalgebra_glm::Vec2;
-use crate::color::Color;
+use nalgebra_glm::{Vec2, Vec3};
+
+pub const MAX_SAMPLES: usize = 8;
 
 pub struct Fragment {
     pub position: Vec2,
-    pub color: Color,
+    pub color: Vec3,
     pub depth: f32,
+    // Bit i is set when sample i of the pixel is inside the primitive
+    pub coverage: u8,
+    pub sample_depths: [f32; MAX_SAMPLES],
+    // Opacity, below 1.0 for pixels partly covered by an antialiased line
+    pub alpha: f32,
 }
 
 impl Fragment {
-    pub fn new(x: f32, y: f32, color: Color, depth: f32) -> Self {
+    pub fn new(x: f32, y: f32, color: Vec3, depth: f32) -> Self {
+        Fragment {
+            position: Vec2::new(x, y),
+            color,
+            depth,
+            coverage: u8::MAX,
+            sample_depths: [depth; MAX_SAMPLES],
+            alpha: 1.0,
+        }
+    }
+
+    pub fn with_alpha(x: f32, y: f32, color: Vec3, depth: f32, alpha: f32) -> Self {
+        Fragment {
+            alpha,
+            ..Fragment::new(x, y, color, depth)
+        }
+    }
+
+    pub fn with_coverage(x: f32, y: f32, color: Vec3, coverage: u8, sample_depths: [f32; MAX_SAMPLES]) -> Self {
+        let depth = sample_depths.iter()
+            .enumerate()
+            .filter(|(i, _)| coverage & (1 << i) != 0)
+            .map(|(_, depth)| *depth)
+            .fold(f32::INFINITY, f32::min);
+
         Fragment {
             position: Vec2::new(x, y),
             color,
             depth,
+            coverage,
+            sample_depths,
+            alpha: 1.0,
         }
     }
 }
@@ -1,12 +1,55 @@
-use nalgebra_glm::{Vec2, Vec3, dot};
-use crate::color::Color;
-use crate::vertex::Vertex;
+use nalgebra_glm::{Vec2, Vec3, Vec4, dot, mat4_to_mat3};
+use crate::color::srgb_to_linear;
+use crate::Uniforms;
 
-pub type FragmentShader = fn(&Vertex, &Vertex, &Vertex, Vec3, Vec3, Vec2) -> Color;
+// Light reaching surfaces from every direction, for material_shader
+const AMBIENT_LIGHT: f32 = 0.1;
+// Direction towards the viewer, who looks down +z
+const VIEW_DIR: Vec3 = Vec3::new(0.0, 0.0, -1.0);
+
+// Light reaching the rings from every direction
+const RING_AMBIENT: f32 = 0.15;
+// Share of the light falling on the rings that gets through their thin parts
+// to the unlit face
+const RING_FORWARD_SCATTER: f32 = 0.6;
+// Ice grains in the rings: cell size in planet radii, fraction of cells with a
+// grain catching the light where the rings are opaque, and how bright it glints
+const SPARKLE_CELL: f32 = 0.012;
+const SPARKLE_FRACTION: f32 = 0.01;
+const SPARKLE_INTENSITY: f32 = 6.0;
+// Times per radian of the light turning around the rings that the glints change
+const SPARKLE_PHASES: f32 = 40.0;
+
+// Shaders return linear HDR color: channels may go above 1.0 and are only
+// tone mapped and gamma encoded when the frame is presented. Palettes below
+// are picked as display (sRGB) colors and converted before lighting.
+pub type FragmentShader = fn(&ShaderInput, &Uniforms) -> Vec3;
+
+// Attributes interpolated for the pixel being shaded
+#[derive(Clone, Copy)]
+pub struct ShaderInput {
+    // Object space position
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub tex_coords: Vec2,
+    // Change in position and tex_coords per pixel step along screen x and y
+    // (dFdx / dFdy), used for texture LOD and to filter procedural detail
+    pub dpos_dx: Vec3,
+    pub dpos_dy: Vec3,
+    pub duv_dx: Vec2,
+    pub duv_dy: Vec2,
+}
+
+impl ShaderInput {
+    // Rough size of this pixel on the surface, in object space units
+    pub fn footprint(&self) -> f32 {
+        self.dpos_dx.magnitude().max(self.dpos_dy.magnitude())
+    }
+}
 
 // Utility functions for noise and patterns
 fn hash(n: f32) -> f32 {
-    let x = (n * 12.9898).sin() * 43758.5453;
+    let x = (n * 12.9898).sin() * 43_758.547;
     x - x.floor()
 }
 
@@ -15,12 +58,12 @@ fn hash_vec3(p: Vec3) -> f32 {
     hash(n)
 }
 
-fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
     let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
     t * t * (3.0 - 2.0 * t)
 }
 
-fn noise(p: Vec3) -> f32 {
+pub fn noise(p: Vec3) -> f32 {
     let i = Vec3::new(p.x.floor(), p.y.floor(), p.z.floor());
     let f = Vec3::new(p.x - i.x, p.y - i.y, p.z - i.z);
     
@@ -53,7 +96,7 @@ fn noise(p: Vec3) -> f32 {
     y1 + (y2 - y1) * u.z
 }
 
-fn fbm(p: Vec3, octaves: u32) -> f32 {
+pub fn fbm(p: Vec3, octaves: u32) -> f32 {
     let mut value = 0.0;
     let mut amplitude = 0.5;
     let mut frequency = 1.0;
@@ -67,8 +110,29 @@ fn fbm(p: Vec3, octaves: u32) -> f32 {
     value
 }
 
+// fbm with octaves too fine for the pixel faded out to their average value.
+// `footprint` is the pixel size in the same units as `p`: an octave starts
+// fading when its features shrink to four pixels and is gone at two.
+pub fn fbm_filtered(p: Vec3, octaves: u32, footprint: f32) -> f32 {
+    let mut value = 0.0;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.0;
+
+    for _ in 0..octaves {
+        let detail = 1.0 - smoothstep(0.25, 0.5, frequency * footprint);
+        let octave = if detail > 0.0 { noise(p * frequency) } else { 0.5 };
+        value += amplitude * (octave * detail + 0.5 * (1.0 - detail));
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    value
+}
+
 // Star/Sun Shader
-pub fn star_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3, normal: Vec3, _tex_coords: Vec2) -> Color {
+pub fn star_shader(input: &ShaderInput, _uniforms: &Uniforms) -> Vec3 {
+    let ShaderInput { position, normal, .. } = *input;
+    let footprint = input.footprint();
     let light_dir = Vec3::new(0.0, 0.0, -1.0);
     let intensity = dot(&normal, &light_dir).max(0.0);
     
@@ -76,7 +140,7 @@ pub fn star_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3, nor
     let base_color = Vec3::new(1.0, 0.7, 0.3);
     
     // Add noise for surface variation
-    let noise_value = fbm(Vec3::new(position.x * 5.0, position.y * 5.0, position.z * 5.0), 3);
+    let noise_value = fbm_filtered(position * 5.0, 3, footprint * 5.0);
     let variation = 0.1 * noise_value;
     
     // Add bright center effect
@@ -86,39 +150,41 @@ pub fn star_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3, nor
     // Add solar flare effect based on angle
     let flare = (normal.z * 0.5 + 0.5).powf(3.0) * 0.2;
     
-    let r = (base_color.x + variation + center_glow + flare).clamp(0.0, 1.0);
-    let g = (base_color.y + variation * 0.5 + center_glow * 0.8 + flare * 0.9).clamp(0.0, 1.0);
-    let b = (base_color.z + variation * 0.3 + center_glow * 0.5).clamp(0.0, 1.0);
+    let surface = srgb_to_linear(Vec3::new(
+        base_color.x + variation + center_glow + flare,
+        base_color.y + variation * 0.5 + center_glow * 0.8 + flare * 0.9,
+        base_color.z + variation * 0.3 + center_glow * 0.5
+    ));
     
     // Apply lighting
     let light_factor = intensity * 0.7 + 0.3;
-    let final_color = Vec3::new(r * light_factor, g * light_factor, b * light_factor);
-    
-    Color::from_float(final_color.x, final_color.y, final_color.z)
+    surface * light_factor
 }
 
 // Rocky Planet Shader (Earth-like)
-pub fn rocky_planet_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3, normal: Vec3, _tex_coords: Vec2) -> Color {
-    let light_dir = Vec3::new(0.0, 0.0, -1.0);
-    let intensity = dot(&normal, &light_dir).max(0.0);
+pub fn rocky_planet_shader(input: &ShaderInput, uniforms: &Uniforms) -> Vec3 {
+    let ShaderInput { position, normal, .. } = *input;
+    let footprint = input.footprint();
+    let light_dir = uniforms.light_dir;
+    let intensity = lit(input, uniforms, dot(&normal, &light_dir));
     
     // Use spherical coordinates for consistent mapping
     let lat = (position.y / position.magnitude()).acos();
     
     // Layer 1: Ocean/Continents base
-    let continent_noise = fbm(Vec3::new(position.x * 2.0, position.y * 2.0, position.z * 2.0), 4);
+    let continent_noise = fbm_filtered(position * 2.0, 4, footprint * 2.0);
     let is_land = continent_noise > 0.1;
     
     // Layer 2: Ocean depth variation
     let ocean_depth = if !is_land {
-        fbm(Vec3::new(position.x * 3.0, position.y * 3.0, position.z * 3.0), 3) * 0.3 + 0.7
+        fbm_filtered(position * 3.0, 3, footprint * 3.0) * 0.3 + 0.7
     } else {
         0.0
     };
     
     // Layer 3: Land elevation
     let elevation = if is_land {
-        fbm(Vec3::new(position.x * 4.0, position.y * 4.0, position.z * 4.0), 3) * 0.5 + 0.5
+        fbm_filtered(position * 4.0, 3, footprint * 4.0) * 0.5 + 0.5
     } else {
         0.0
     };
@@ -175,15 +241,21 @@ pub fn rocky_planet_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: V
     
     // Apply lighting with ambient
     let light_factor = intensity * 0.8 + 0.2;
-    let final_color = Vec3::new(r * light_factor, g * light_factor, b * light_factor);
-    
-    Color::from_float(final_color.x, final_color.y, final_color.z)
+    srgb_to_linear(Vec3::new(r, g, b)) * light_factor
 }
 
 // Gas Giant Shader (Jupiter-like)
-pub fn gas_giant_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3, normal: Vec3, _tex_coords: Vec2) -> Color {
-    let light_dir = Vec3::new(0.0, 0.0, -1.0);
-    let intensity = dot(&normal, &light_dir).max(0.0);
+pub fn gas_giant_shader(input: &ShaderInput, uniforms: &Uniforms) -> Vec3 {
+    let ShaderInput { position, normal, .. } = *input;
+    let footprint = input.footprint();
+    let light_dir = uniforms.light_dir;
+    // Rings in the uniforms shade the bands under them. Taking the darker of
+    // their shadow and the other bodies' keeps the two from stacking.
+    let cos_angle = dot(&normal, &light_dir);
+    let ring_shadow = uniforms.rings.map_or(1.0, |rings| {
+        rings.shadow_on_planet(position, object_light_dir(uniforms), footprint)
+    });
+    let intensity = cos_angle.max(0.0) * ring_shadow.min(shadow(input, uniforms, cos_angle));
     
     // Use latitude for banding
     let lat = position.y / position.magnitude();
@@ -193,11 +265,11 @@ pub fn gas_giant_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3
     let band = (lat * band_freq).sin() * 0.5 + 0.5;
     
     // Layer 2: Turbulence for swirls
-    let turbulence = fbm(Vec3::new(position.x * 3.0, position.y * 3.0, position.z * 3.0), 4);
+    let turbulence = fbm_filtered(position * 3.0, 4, footprint * 3.0);
     let swirl = (turbulence * 2.0 - 1.0) * 0.3;
     
     // Layer 3: Color variation within bands
-    let color_variation = fbm(Vec3::new(position.x * 5.0, position.y * 5.0, position.z * 5.0), 3) * 0.2;
+    let color_variation = fbm_filtered(position * 5.0, 3, footprint * 5.0) * 0.2;
     
     // Layer 4: Great Red Spot-like feature
     let spot_pos = Vec3::new(0.0, 0.3, 0.8);
@@ -226,25 +298,21 @@ pub fn gas_giant_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3
     let final_base = varied_color * (1.0 - spot) + red_spot * spot;
     
     // Apply lighting
-    let final_color = final_base * (intensity * 0.7 + 0.3);
-    
-    Color::from_float(
-        final_color.x.clamp(0.0, 1.0),
-        final_color.y.clamp(0.0, 1.0),
-        final_color.z.clamp(0.0, 1.0)
-    )
+    srgb_to_linear(final_base) * (intensity * 0.7 + 0.3)
 }
 
 // Moon Shader (simple gray with craters)
-pub fn moon_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3, normal: Vec3, _tex_coords: Vec2) -> Color {
-    let light_dir = Vec3::new(0.0, 0.0, -1.0);
-    let intensity = dot(&normal, &light_dir).max(0.0);
+pub fn moon_shader(input: &ShaderInput, uniforms: &Uniforms) -> Vec3 {
+    let ShaderInput { position, normal, .. } = *input;
+    let footprint = input.footprint();
+    let light_dir = uniforms.light_dir;
+    let intensity = lit(input, uniforms, dot(&normal, &light_dir));
     
     // Base gray color
     let base_gray = 0.5;
     
     // Add crater-like noise
-    let craters = fbm(Vec3::new(position.x * 8.0, position.y * 8.0, position.z * 8.0), 4);
+    let craters = fbm_filtered(position * 8.0, 4, footprint * 8.0);
     let crater_depth = (craters - 0.5).abs() * 2.0;
     let crater = if crater_depth > 0.7 {
         crater_depth * 0.3
@@ -255,41 +323,117 @@ pub fn moon_shader(_v1: &Vertex, _v2: &Vertex, _v3: &Vertex, position: Vec3, nor
     let gray = (base_gray - crater).clamp(0.2, 0.8);
     
     // Apply lighting
-    let final_gray = gray * (intensity * 0.9 + 0.1);
-    
-    Color::from_float(final_gray, final_gray, final_gray)
+    srgb_to_linear(Vec3::repeat(gray)) * (intensity * 0.9 + 0.1)
 }
 
-// Ring Shader (simple gradient)
-pub fn ring_shader(v1: &Vertex, v2: &Vertex, v3: &Vertex, position: Vec3, normal: Vec3, tex_coords: Vec2) -> Color {
-    let light_dir = Vec3::new(0.0, 0.0, -1.0);
-    let intensity = dot(&normal, &light_dir).max(0.0);
-    
-    // Use texture coordinates for radial gradient
-    let radial = tex_coords.y; // 0.0 = inner, 1.0 = outer
-    
-    // Dusty brown-gray color
-    let inner_color = Vec3::new(0.4, 0.35, 0.3);
-    let outer_color = Vec3::new(0.5, 0.45, 0.4);
-    
-    let color = Vec3::new(
-        inner_color.x * (1.0 - radial) + outer_color.x * radial,
-        inner_color.y * (1.0 - radial) + outer_color.y * radial,
-        inner_color.z * (1.0 - radial) + outer_color.z * radial
-    );
-    
-    // Add some variation
-    let variation = fbm(Vec3::new(position.x * 10.0, position.y * 10.0, position.z * 10.0), 2) * 0.1;
-    let final_color = Vec3::new(color.x + variation, color.y + variation, color.z + variation);
-    
-    // Apply lighting with transparency effect
-    let light_factor = intensity * 0.6 + 0.4;
-    let ring_final = Vec3::new(final_color.x * light_factor, final_color.y * light_factor, final_color.z * light_factor);
-    
-    Color::from_float(
-        ring_final.x.clamp(0.0, 1.0),
-        ring_final.y.clamp(0.0, 1.0),
-        ring_final.z.clamp(0.0, 1.0)
-    )
+// Ring Shader: particles as dense as the ring profile in the uniforms, lit
+// past the planet's shadow, with single ice grains glinting
+pub fn ring_shader(input: &ShaderInput, uniforms: &Uniforms) -> Vec3 {
+    let ShaderInput { position, normal, .. } = *input;
+    let footprint = input.footprint();
+    let facing_light = dot(&normal, &uniforms.light_dir);
+    let Some(rings) = uniforms.rings else {
+        return srgb_to_linear(Vec3::new(0.45, 0.4, 0.35)) * (facing_light.abs() * 0.6 + 0.4);
+    };
+    let light_dir = object_light_dir(uniforms);
+    let radius = Vec2::new(position.x, position.z).magnitude();
+    let opacity = rings.opacity(radius, footprint);
+
+    // The face towards the light reflects off the particles, more the more
+    // there are. From the other face only light slipping between them shows,
+    // so the densest bands turn dark there.
+    let lit_face = facing_light * dot(&normal, &VIEW_DIR) >= 0.0;
+    let brightness = if lit_face { opacity } else { opacity * (1.0 - opacity) * RING_FORWARD_SCATTER };
+    let planet_shadow = rings.planet_shadow(position, light_dir);
+    let direct = facing_light.abs() * planet_shadow.min(shadow(input, uniforms, facing_light.abs()));
+
+    // Dusty where the rings thin out, icy in the dense bands
+    let dusty = Vec3::new(0.45, 0.40, 0.34);
+    let icy = Vec3::new(0.86, 0.79, 0.66);
+    let albedo = srgb_to_linear(dusty + (icy - dusty) * opacity);
+
+    // A few cells have a grain catching the light, different ones as the
+    // light turns around the rings. Seen edge-on the cells squash into lines,
+    // so they fade to their average once smaller than the pixel across them.
+    let grain_footprint = input.dpos_dx.magnitude().min(input.dpos_dy.magnitude());
+    let phase = (light_dir.z.atan2(light_dir.x) * SPARKLE_PHASES).floor();
+    let cell = Vec3::new((position.x / SPARKLE_CELL).floor(), (position.z / SPARKLE_CELL).floor(), phase);
+    let chance = SPARKLE_FRACTION * opacity;
+    let glint = if hash_vec3(cell) > 1.0 - chance { 1.0 } else { 0.0 };
+    let detail = 1.0 - smoothstep(SPARKLE_CELL, SPARKLE_CELL * 2.0, grain_footprint);
+    let sparkle = if lit_face {
+        (glint * detail + chance * (1.0 - detail)) * SPARKLE_INTENSITY * direct
+    } else {
+        0.0
+    };
+
+    albedo * brightness * (direct + RING_AMBIENT) + Vec3::repeat(sparkle)
+}
+
+// Fraction of the light reaching the point being shaded past the casters in
+// the shadow map and the spheres in the eclipses, for whichever the uniforms
+// have. `cos_angle` is the cosine between the normal and the direction
+// towards the light.
+fn shadow(input: &ShaderInput, uniforms: &Uniforms, cos_angle: f32) -> f32 {
+    if uniforms.shadow_map.is_none() && uniforms.eclipses.is_none() {
+        return 1.0;
+    }
+    let p = uniforms.model_matrix * Vec4::new(input.position.x, input.position.y, input.position.z, 1.0);
+    let p = p.xyz() / p.w;
+    let mapped = uniforms.shadow_map.map_or(1.0, |shadow_map| shadow_map.visibility(p, cos_angle));
+    let eclipsed = uniforms.eclipses.map_or(1.0, |eclipses| eclipses.visibility(p));
+    mapped * eclipsed
+}
+
+// Lambert term for `cos_angle`, darkened where the point is in shadow
+fn lit(input: &ShaderInput, uniforms: &Uniforms, cos_angle: f32) -> f32 {
+    if cos_angle <= 0.0 {
+        return 0.0;
+    }
+    cos_angle * shadow(input, uniforms, cos_angle)
 }
 
+// `uniforms.light_dir` in the object space of the mesh being drawn
+fn object_light_dir(uniforms: &Uniforms) -> Vec3 {
+    let model_view = mat4_to_mat3(&(uniforms.view_matrix * uniforms.model_matrix));
+    (model_view.transpose() * uniforms.light_dir).normalize()
+}
+
+// Lambert shaded albedo from the texture bound in the uniforms
+pub fn textured_shader(input: &ShaderInput, uniforms: &Uniforms) -> Vec3 {
+    let intensity = lit(input, uniforms, dot(&input.normal, &uniforms.light_dir));
+
+    let albedo = match uniforms.texture {
+        Some(texture) => texture.sample(input.tex_coords, input.duv_dx, input.duv_dy),
+        None => Vec3::repeat(0.5),
+    };
+
+    albedo * (intensity * 0.8 + 0.2)
+}
+
+// Blinn-Phong lighting of the material in the uniforms, seen from -z
+pub fn material_shader(input: &ShaderInput, uniforms: &Uniforms) -> Vec3 {
+    let light_dir = uniforms.light_dir;
+    let half_dir = (light_dir + VIEW_DIR).try_normalize(1e-6).unwrap_or(VIEW_DIR);
+    let normal = input.normal;
+
+    let Some(material) = uniforms.material else {
+        return Vec3::repeat(0.8) * (lit(input, uniforms, dot(&normal, &light_dir)) + AMBIENT_LIGHT);
+    };
+
+    let albedo = match &material.diffuse_texture {
+        Some(texture) => material.diffuse.component_mul(&texture.sample(input.tex_coords, input.duv_dx, input.duv_dy)),
+        None => material.diffuse,
+    };
+
+    let cos_angle = dot(&normal, &light_dir);
+    let visibility = shadow(input, uniforms, cos_angle);
+    let diffuse = cos_angle.max(0.0) * visibility;
+    let specular = if diffuse > 0.0 {
+        dot(&normal, &half_dir).max(0.0).powf(material.shininess.max(1.0)) * visibility
+    } else {
+        0.0
+    };
+
+    material.ambient.component_mul(&albedo) * AMBIENT_LIGHT + albedo * diffuse + material.specular * specular
+}
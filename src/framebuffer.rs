@@ -1,12 +1,54 @@
 // framebuffer.rs
 
+use nalgebra_glm::{Vec2, Vec3};
+use crate::color::{Color, srgb_to_linear, linear_to_srgb};
+use crate::fragment::MAX_SAMPLES;
+use crate::tonemap::{tone_map, ToneMapSettings};
+
+// Pixel rectangle, `x..x + width` by `y..y + height`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x - self.x < self.width && y >= self.y && y - self.y < self.height
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
 pub struct Framebuffer {
     pub width: usize,
     pub height: usize,
+    // Final 8-bit image shown in the window, written by `present`
     pub buffer: Vec<u32>,
+    // Linear float scene color, allowed to go above 1.0 for bright/emissive surfaces
+    pub hdr: Vec<Vec3>,
     pub zbuffer: Vec<f32>,
+    // Multisample storage, `sample_count` entries per pixel. Only used with more
+    // than one sample; `resolve` averages it down into `hdr` and `zbuffer`.
+    sample_positions: Vec<Vec2>,
+    sample_color: Vec<Vec3>,
+    sample_depth: Vec<f32>,
+    // Drawing outside this rectangle is discarded; `None` allows the whole buffer
+    scissor: Option<Rect>,
     background_color: u32,
-    current_color: u32,
+    current_color: Vec3,
 }
 
 impl Framebuffer {
@@ -15,28 +57,180 @@ impl Framebuffer {
             width,
             height,
             buffer: vec![0; width * height],
+            hdr: vec![Vec3::zeros(); width * height],
             zbuffer: vec![f32::INFINITY; width * height],
+            sample_positions: vec![Vec2::new(0.5, 0.5)],
+            sample_color: Vec::new(),
+            sample_depth: Vec::new(),
+            scissor: None,
             background_color: 0x000000,
-            current_color: 0xFFFFFF,
+            current_color: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.sample_positions.len()
+    }
+
+    pub fn sample_positions(&self) -> &[Vec2] {
+        &self.sample_positions
+    }
+
+    // Switches the number of coverage samples per pixel (one disables multisampling)
+    pub fn set_sample_positions(&mut self, positions: Vec<Vec2>) {
+        let count = positions.len();
+        self.sample_positions = positions;
+
+        if count > 1 {
+            self.sample_color = vec![Vec3::zeros(); self.width * self.height * count];
+            self.sample_depth = vec![f32::INFINITY; self.width * self.height * count];
+        } else {
+            self.sample_color = Vec::new();
+            self.sample_depth = Vec::new();
+        }
+    }
+
+    pub fn viewport(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    pub fn set_scissor(&mut self, scissor: Option<Rect>) {
+        self.scissor = scissor;
+    }
+
+    // Area primitives may draw into: the viewport, limited by the scissor if set
+    pub fn clip_rect(&self) -> Rect {
+        match self.scissor {
+            Some(scissor) => self.viewport().intersect(&scissor),
+            None => self.viewport(),
         }
     }
 
     pub fn clear(&mut self) {
-        for pixel in self.buffer.iter_mut() {
-            *pixel = self.background_color;
+        let background = srgb_to_linear(Color::from_hex(self.background_color).to_vec3());
+        for pixel in self.hdr.iter_mut() {
+            *pixel = background;
         }
+        self.clear_depth();
+    }
+
+    fn clear_depth(&mut self) {
         for depth in self.zbuffer.iter_mut() {
             *depth = f32::INFINITY;
         }
+        for depth in self.sample_depth.iter_mut() {
+            *depth = f32::INFINITY;
+        }
     }
 
-    pub fn point(&mut self, x: usize, y: usize, depth: f32) {
-        if x < self.width && y < self.height {
-            let index = y * self.width + x;
+    // Clears depth and fills every pixel with the color returned for it
+    pub fn clear_with<F: Fn(usize, usize) -> Vec3>(&mut self, color_at: F) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.hdr[y * self.width + x] = color_at(x, y);
+            }
+        }
+        self.clear_depth();
+    }
+
+    // Depth tests and writes each sample whose bit is set in `coverage`
+    pub fn point_coverage(&mut self, x: usize, y: usize, coverage: u8, sample_depths: &[f32; MAX_SAMPLES]) {
+        if !self.clip_rect().contains(x, y) {
+            return;
+        }
+        let index = y * self.width + x;
+        let count = self.sample_count();
+
+        if count == 1 {
+            if coverage & 1 != 0 && self.zbuffer[index] > sample_depths[0] {
+                self.hdr[index] = self.current_color;
+                self.zbuffer[index] = sample_depths[0];
+            }
+            return;
+        }
+
+        for (sample, depth) in sample_depths.iter().enumerate().take(count) {
+            let sample_index = index * count + sample;
+            if coverage & (1 << sample) != 0 && self.sample_depth[sample_index] > *depth {
+                self.sample_color[sample_index] = self.current_color;
+                self.sample_depth[sample_index] = *depth;
+            }
+        }
+    }
+
+    // Depth tests every sample of the pixel and mixes the current color over
+    // what is there by `alpha`. Used for antialiased lines, which leave depth
+    // as it was so overlapping lines all blend in instead of hiding each other.
+    pub fn blend_point(&mut self, x: usize, y: usize, depth: f32, alpha: f32) {
+        if !self.clip_rect().contains(x, y) {
+            return;
+        }
+        let index = y * self.width + x;
+        let count = self.sample_count();
+
+        if count == 1 {
             if self.zbuffer[index] > depth {
-                self.buffer[index] = self.current_color;
-                self.zbuffer[index] = depth;
+                self.hdr[index] = self.hdr[index] * (1.0 - alpha) + self.current_color * alpha;
             }
+            return;
+        }
+
+        for sample_index in index * count..(index + 1) * count {
+            let sample_depth = self.sample_depth[sample_index];
+            if sample_depth > depth {
+                // Samples nothing was drawn to show the background held in `hdr`
+                let below = if sample_depth < f32::INFINITY { self.sample_color[sample_index] } else { self.hdr[index] };
+                self.sample_color[sample_index] = below * (1.0 - alpha) + self.current_color * alpha;
+                // A background sample has to be marked as drawn for `resolve`
+                // to use its color, but stays behind everything
+                if sample_depth == f32::INFINITY {
+                    self.sample_depth[sample_index] = f32::MAX;
+                }
+            }
+        }
+    }
+
+    // Averages the samples of each pixel into `hdr`. Samples nothing was drawn to
+    // take the background already in `hdr`, so edges blend against it.
+    pub fn resolve(&mut self) {
+        let count = self.sample_count();
+        if count == 1 {
+            return;
+        }
+
+        for index in 0..self.width * self.height {
+            let background = self.hdr[index];
+            let mut sum = Vec3::zeros();
+            let mut nearest = f32::INFINITY;
+
+            for sample in index * count..(index + 1) * count {
+                let depth = self.sample_depth[sample];
+                if depth < f32::INFINITY {
+                    sum += self.sample_color[sample];
+                    nearest = nearest.min(depth);
+                } else {
+                    sum += background;
+                }
+            }
+
+            self.hdr[index] = sum / count as f32;
+            self.zbuffer[index] = nearest;
+        }
+    }
+
+    // Adds light on top of the current color without touching depth
+    pub fn add_light(&mut self, x: usize, y: usize, light: Vec3) {
+        if x < self.width && y < self.height {
+            self.hdr[y * self.width + x] += light;
+        }
+    }
+
+    // Tone maps the linear scene color and gamma encodes it into the 8-bit
+    // buffer shown in the window
+    pub fn present(&mut self, settings: &ToneMapSettings) {
+        for (pixel, color) in self.buffer.iter_mut().zip(self.hdr.iter()) {
+            let display = linear_to_srgb(tone_map(*color, settings));
+            *pixel = Color::from_float(display.x, display.y, display.z).to_hex();
         }
     }
 
@@ -44,7 +238,49 @@ impl Framebuffer {
         self.background_color = color;
     }
 
-    pub fn set_current_color(&mut self, color: u32) {
+    pub fn set_current_color(&mut self, color: Vec3) {
         self.current_color = color;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scissor_is_clipped_to_the_viewport() {
+        let mut framebuffer = Framebuffer::new(20, 10);
+        assert_eq!(framebuffer.clip_rect(), framebuffer.viewport());
+
+        // Hangs off the right and bottom edges
+        framebuffer.set_scissor(Some(Rect::new(15, 4, 10, 10)));
+        assert_eq!(framebuffer.clip_rect(), Rect::new(15, 4, 5, 6));
+
+        // Nothing is drawn outside it
+        framebuffer.blend_point(3, 3, 1.0, 1.0);
+        framebuffer.blend_point(16, 5, 1.0, 1.0);
+        assert_eq!(framebuffer.hdr[3 * 20 + 3], Vec3::zeros());
+        assert_eq!(framebuffer.hdr[5 * 20 + 16], Vec3::repeat(1.0));
+
+        framebuffer.set_scissor(None);
+        assert_eq!(framebuffer.clip_rect(), framebuffer.viewport());
+    }
+
+    #[test]
+    fn blended_lines_leave_depth_alone() {
+        for samples in [vec![Vec2::new(0.5, 0.5)], vec![Vec2::new(0.25, 0.25), Vec2::new(0.75, 0.75)]] {
+            let mut framebuffer = Framebuffer::new(2, 2);
+            framebuffer.set_sample_positions(samples);
+
+            // Two lines crossing at the same depth both show
+            framebuffer.set_current_color(Vec3::new(1.0, 0.0, 0.0));
+            framebuffer.blend_point(0, 0, 5.0, 0.5);
+            framebuffer.set_current_color(Vec3::new(0.0, 1.0, 0.0));
+            framebuffer.blend_point(0, 0, 5.0, 0.5);
+            framebuffer.resolve();
+
+            assert_eq!(framebuffer.hdr[0], Vec3::new(0.25, 0.5, 0.0));
+            assert!(framebuffer.zbuffer[0] > 1e30);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use nalgebra_glm::Vec3;
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+
+// Tuning values from the FXAA 3.11 "quality" preset
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const EDGE_THRESHOLD_MAX: f32 = 0.125;
+const SUBPIXEL_QUALITY: f32 = 0.75;
+// Step sizes used while walking along an edge looking for its ends
+const EDGE_STEPS: [f32; 10] = [1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 4.0, 8.0];
+
+struct Image {
+    width: usize,
+    height: usize,
+    colors: Vec<Vec3>,
+    luma: Vec<f32>,
+}
+
+impl Image {
+    fn luma_at(&self, x: i32, y: i32) -> f32 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.luma[y * self.width + x]
+    }
+
+    fn color_at(&self, x: i32, y: i32) -> Vec3 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.colors[y * self.width + x]
+    }
+
+    // Bilinear lookups in pixel coordinates, pixel centers at +0.5
+    fn luma_linear(&self, x: f32, y: f32) -> f32 {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self.luma_at(x0, y0) * (1.0 - tx) + self.luma_at(x0 + 1, y0) * tx;
+        let bottom = self.luma_at(x0, y0 + 1) * (1.0 - tx) + self.luma_at(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    fn color_linear(&self, x: f32, y: f32) -> Vec3 {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self.color_at(x0, y0) * (1.0 - tx) + self.color_at(x0 + 1, y0) * tx;
+        let bottom = self.color_at(x0, y0 + 1) * (1.0 - tx) + self.color_at(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+// Smooths aliased edges in the presented (tone mapped, gamma encoded) buffer
+pub fn apply_fxaa(framebuffer: &mut Framebuffer) {
+    let colors: Vec<Vec3> = framebuffer.buffer.iter()
+        .map(|pixel| Color::from_hex(*pixel).to_vec3())
+        .collect();
+    let luma = colors.iter()
+        .map(|c| c.x * 0.299 + c.y * 0.587 + c.z * 0.114)
+        .collect();
+    let image = Image {
+        width: framebuffer.width,
+        height: framebuffer.height,
+        colors,
+        luma,
+    };
+
+    for y in 0..framebuffer.height {
+        for x in 0..framebuffer.width {
+            let color = fxaa_pixel(&image, x as i32, y as i32);
+            framebuffer.buffer[y * framebuffer.width + x] = Color::from_float(color.x, color.y, color.z).to_hex();
+        }
+    }
+}
+
+fn fxaa_pixel(image: &Image, x: i32, y: i32) -> Vec3 {
+    let center = image.luma_at(x, y);
+    let down = image.luma_at(x, y + 1);
+    let up = image.luma_at(x, y - 1);
+    let left = image.luma_at(x - 1, y);
+    let right = image.luma_at(x + 1, y);
+
+    let luma_min = center.min(down).min(up).min(left).min(right);
+    let luma_max = center.max(down).max(up).max(left).max(right);
+    let range = luma_max - luma_min;
+
+    // Skip flat areas
+    if range < EDGE_THRESHOLD_MIN.max(luma_max * EDGE_THRESHOLD_MAX) {
+        return image.color_at(x, y);
+    }
+
+    let down_left = image.luma_at(x - 1, y + 1);
+    let up_right = image.luma_at(x + 1, y - 1);
+    let up_left = image.luma_at(x - 1, y - 1);
+    let down_right = image.luma_at(x + 1, y + 1);
+
+    let down_up = down + up;
+    let left_right = left + right;
+    let left_corners = down_left + up_left;
+    let down_corners = down_left + down_right;
+    let right_corners = down_right + up_right;
+    let up_corners = up_right + up_left;
+
+    // Is the edge closer to horizontal or vertical?
+    let edge_horizontal = (-2.0 * left + left_corners).abs()
+        + (-2.0 * center + down_up).abs() * 2.0
+        + (-2.0 * right + right_corners).abs();
+    let edge_vertical = (-2.0 * up + up_corners).abs()
+        + (-2.0 * center + left_right).abs() * 2.0
+        + (-2.0 * down + down_corners).abs();
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // Pick the side of the edge with the steepest gradient
+    let luma1 = if is_horizontal { up } else { left };
+    let luma2 = if is_horizontal { down } else { right };
+    let gradient1 = luma1 - center;
+    let gradient2 = luma2 - center;
+    let is1_steepest = gradient1.abs() >= gradient2.abs();
+    let gradient_scaled = 0.25 * gradient1.abs().max(gradient2.abs());
+
+    let (step_length, local_average) = if is1_steepest {
+        (-1.0, 0.5 * (luma1 + center))
+    } else {
+        (1.0, 0.5 * (luma2 + center))
+    };
+
+    // Start halfway between this pixel and the one across the edge
+    let (mut cx, mut cy) = (x as f32 + 0.5, y as f32 + 0.5);
+    if is_horizontal {
+        cy += step_length * 0.5;
+    } else {
+        cx += step_length * 0.5;
+    }
+    let (ox, oy) = if is_horizontal { (1.0, 0.0) } else { (0.0, 1.0) };
+
+    // Walk both ways along the edge until the luma changes enough
+    let (mut x1, mut y1) = (cx, cy);
+    let (mut x2, mut y2) = (cx, cy);
+    let mut luma_end1 = 0.0;
+    let mut luma_end2 = 0.0;
+    let mut reached1 = false;
+    let mut reached2 = false;
+
+    for step in EDGE_STEPS {
+        if !reached1 {
+            x1 -= ox * step;
+            y1 -= oy * step;
+            luma_end1 = image.luma_linear(x1, y1) - local_average;
+            reached1 = luma_end1.abs() >= gradient_scaled;
+        }
+        if !reached2 {
+            x2 += ox * step;
+            y2 += oy * step;
+            luma_end2 = image.luma_linear(x2, y2) - local_average;
+            reached2 = luma_end2.abs() >= gradient_scaled;
+        }
+        if reached1 && reached2 {
+            break;
+        }
+    }
+
+    let pixel_x = x as f32 + 0.5;
+    let pixel_y = y as f32 + 0.5;
+    let (distance1, distance2) = if is_horizontal {
+        (pixel_x - x1, x2 - pixel_x)
+    } else {
+        (pixel_y - y1, y2 - pixel_y)
+    };
+
+    let is_direction1 = distance1 < distance2;
+    let distance_final = distance1.min(distance2);
+    let edge_thickness = distance1 + distance2;
+    let pixel_offset = -distance_final / edge_thickness + 0.5;
+
+    // Only move towards the edge end whose luma variation matches ours
+    let is_center_smaller = center < local_average;
+    let end_luma = if is_direction1 { luma_end1 } else { luma_end2 };
+    let correct_variation = (end_luma < 0.0) != is_center_smaller;
+    let edge_offset = if correct_variation { pixel_offset } else { 0.0 };
+
+    // Sub-pixel aliasing: compare against the 3x3 neighbourhood average
+    let luma_average = (2.0 * (down_up + left_right) + left_corners + right_corners) / 12.0;
+    let subpixel1 = ((luma_average - center).abs() / range).clamp(0.0, 1.0);
+    let subpixel2 = (-2.0 * subpixel1 + 3.0) * subpixel1 * subpixel1;
+    let subpixel_offset = subpixel2 * subpixel2 * SUBPIXEL_QUALITY;
+
+    let final_offset = edge_offset.max(subpixel_offset);
+    if is_horizontal {
+        image.color_linear(pixel_x, pixel_y + final_offset * step_length)
+    } else {
+        image.color_linear(pixel_x + final_offset * step_length, pixel_y)
+    }
+}
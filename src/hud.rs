@@ -0,0 +1,100 @@
+use std::time::Instant;
+
+use crate::framebuffer::Framebuffer;
+use crate::text::{draw_text, line_height, shade_rect, text_width};
+
+const HUD_SCALE: usize = 2;
+const HUD_MARGIN: usize = 10;
+const HUD_PADDING: usize = 8;
+const TEXT_COLOR: u32 = 0xFFFFFF;
+const HELP_COLOR: u32 = 0xA0A0B0;
+
+const KEY_HELP: [&str; 16] = [
+    "1 STAR  2 PLANET  3 GAS GIANT  4 MODEL  0 ALL",
+    "N NEXT MODEL",
+    "ARROWS ROTATE CAMERA",
+    "TAB SELECT BODY  S SPHERE MESH",
+    "B CYCLE BACKGROUND",
+    "G TOGGLE BLOOM",
+    "T TONEMAP  -/= EXPOSURE",
+    "M CYCLE ANTI-ALIASING",
+    "V CYCLE DEBUG VIEW",
+    "O TOGGLE ORBITS",
+    "L CYCLE SHADOWS",
+    "C TOGGLE SCISSOR",
+    "F CYCLE TEXTURE FILTER",
+    "X EXPORT SCENE TO OBJ/PLY",
+    "H TOGGLE HUD",
+    "ESC QUIT",
+];
+
+pub struct Hud {
+    pub visible: bool,
+    fps: f32,
+    last_frame: Instant,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Hud {
+            visible: true,
+            fps: 0.0,
+            last_frame: Instant::now(),
+        }
+    }
+
+    // Call once per frame to update the smoothed frame rate
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let delta = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        if delta > 0.0 {
+            let instant_fps = 1.0 / delta;
+            self.fps = if self.fps == 0.0 {
+                instant_fps
+            } else {
+                self.fps * 0.9 + instant_fps * 0.1
+            };
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // Draws the frame rate followed by the given status lines and the key help
+    pub fn draw(&self, framebuffer: &mut Framebuffer, status: &[String]) {
+        if !self.visible {
+            return;
+        }
+
+        let fps = format!("FPS: {:.0}", self.fps);
+
+        let lines: Vec<(&str, u32)> = std::iter::once(fps.as_str())
+            .chain(status.iter().map(|line| line.as_str()))
+            .map(|line| (line, TEXT_COLOR))
+            .chain(KEY_HELP.iter().map(|line| (*line, HELP_COLOR)))
+            .collect();
+
+        let width = lines.iter()
+            .map(|(line, _)| text_width(line, HUD_SCALE))
+            .max()
+            .unwrap_or(0);
+        let height = lines.len() * line_height(HUD_SCALE);
+
+        shade_rect(
+            framebuffer,
+            HUD_MARGIN,
+            HUD_MARGIN,
+            width + HUD_PADDING * 2,
+            height + HUD_PADDING * 2,
+        );
+
+        let mut y = HUD_MARGIN + HUD_PADDING;
+        for (line, color) in lines {
+            draw_text(framebuffer, HUD_MARGIN + HUD_PADDING, y, line, color, HUD_SCALE);
+            y += line_height(HUD_SCALE);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use nalgebra_glm::Vec3;
+use crate::color::srgb_to_linear;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+// Decoded image with 0.0 - 1.0 float channels, rows stored top to bottom
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Pixels in an image of the size given in a file header, which must have at
+// least one and not so many they can't be addressed
+fn pixel_count(width: usize, height: usize) -> Result<usize> {
+    if width == 0 || height == 0 {
+        return Err(invalid("image has no pixels"));
+    }
+    width.checked_mul(height).ok_or_else(|| invalid("image is too large"))
+}
+
+// `size` bytes from `pos` on, or an error if the data ends first
+fn slice<'a>(bytes: &'a [u8], pos: usize, size: usize, message: &str) -> Result<&'a [u8]> {
+    bytes.get(pos..).and_then(|rest| rest.get(..size)).ok_or_else(|| invalid(message))
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        Image { width, height, pixels }
+    }
+
+    // Loads a PPM, TGA or PNG file, picked by extension
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        let extension = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        let image = match extension.as_str() {
+            "ppm" => Image::from_ppm(&bytes)?,
+            "tga" => Image::from_tga(&bytes)?,
+            "png" => Image::from_png(&bytes)?,
+            _ => return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("unsupported image format: {}", path.display()),
+            )),
+        };
+        // Sampling and mipmapping need at least one pixel
+        pixel_count(image.width, image.height)?;
+        Ok(image)
+    }
+
+    pub fn from_ppm(bytes: &[u8]) -> Result<Self> {
+        let mut pos = 0;
+
+        let next_token = |pos: &mut usize| -> Result<String> {
+            // Skip whitespace and comments
+            loop {
+                while *pos < bytes.len() && bytes[*pos].is_ascii_whitespace() {
+                    *pos += 1;
+                }
+                if *pos < bytes.len() && bytes[*pos] == b'#' {
+                    while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                        *pos += 1;
+                    }
+                } else {
+                    break;
+                }
+            }
+            let start = *pos;
+            while *pos < bytes.len() && !bytes[*pos].is_ascii_whitespace() {
+                *pos += 1;
+            }
+            if start == *pos {
+                return Err(invalid("unexpected end of PPM header"));
+            }
+            Ok(String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+        };
+
+        let magic = next_token(&mut pos)?;
+        let parse = |token: String| token.parse::<usize>().map_err(|_| invalid("bad number in PPM"));
+        let width = parse(next_token(&mut pos)?)?;
+        let height = parse(next_token(&mut pos)?)?;
+        let max_value = parse(next_token(&mut pos)?)?.max(1) as f32;
+
+        let count = pixel_count(width, height)?;
+        // Every pixel takes at least a byte of the file, which bounds what a
+        // bad header can make us allocate
+        let mut pixels = Vec::with_capacity(count.min(bytes.len()));
+
+        match magic.as_str() {
+            "P3" => {
+                for _ in 0..count {
+                    let r = parse(next_token(&mut pos)?)? as f32;
+                    let g = parse(next_token(&mut pos)?)? as f32;
+                    let b = parse(next_token(&mut pos)?)? as f32;
+                    pixels.push(Vec3::new(r, g, b) / max_value);
+                }
+            }
+            "P6" => {
+                // Exactly one whitespace byte separates the header from the data
+                pos += 1;
+                let wide = max_value > 255.0;
+                let channel_size = if wide { 2 } else { 1 };
+                let size = count.checked_mul(3 * channel_size).ok_or_else(|| invalid("image is too large"))?;
+                let data = slice(bytes, pos, size, "truncated PPM data")?;
+
+                let channel = |i: usize| -> f32 {
+                    if wide {
+                        u16::from_be_bytes([data[i * 2], data[i * 2 + 1]]) as f32
+                    } else {
+                        data[i] as f32
+                    }
+                };
+                for i in 0..count {
+                    pixels.push(Vec3::new(channel(i * 3), channel(i * 3 + 1), channel(i * 3 + 2)) / max_value);
+                }
+            }
+            _ => return Err(invalid("only P3 and P6 PPM files are supported")),
+        }
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    pub fn from_tga(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 18 {
+            return Err(invalid("truncated TGA header"));
+        }
+
+        let id_length = bytes[0] as usize;
+        let color_map_type = bytes[1];
+        let image_type = bytes[2];
+        let width = u16::from_le_bytes([bytes[12], bytes[13]]) as usize;
+        let height = u16::from_le_bytes([bytes[14], bytes[15]]) as usize;
+        let bits_per_pixel = bytes[16];
+        let descriptor = bytes[17];
+
+        if color_map_type != 0 {
+            return Err(invalid("color-mapped TGA files are not supported"));
+        }
+        let rle = match image_type {
+            2 | 3 => false,
+            10 | 11 => true,
+            _ => return Err(invalid("only truecolor and grayscale TGA files are supported")),
+        };
+        let bytes_per_pixel = match bits_per_pixel {
+            8 | 24 | 32 => bits_per_pixel as usize / 8,
+            _ => return Err(invalid("unsupported TGA pixel depth")),
+        };
+
+        let count = pixel_count(width, height)?;
+        let size = count.checked_mul(bytes_per_pixel).ok_or_else(|| invalid("image is too large"))?;
+        let mut raw = Vec::with_capacity(size.min(bytes.len()));
+        let mut pos = 18 + id_length;
+
+        if rle {
+            while raw.len() < size {
+                let header = *bytes.get(pos).ok_or_else(|| invalid("truncated TGA data"))?;
+                pos += 1;
+                let run = (header & 0x7F) as usize + 1;
+
+                if header & 0x80 != 0 {
+                    let pixel = slice(bytes, pos, bytes_per_pixel, "truncated TGA data")?;
+                    for _ in 0..run {
+                        raw.extend_from_slice(pixel);
+                    }
+                    pos += bytes_per_pixel;
+                } else {
+                    let packet = slice(bytes, pos, run * bytes_per_pixel, "truncated TGA data")?;
+                    raw.extend_from_slice(packet);
+                    pos += run * bytes_per_pixel;
+                }
+            }
+            raw.truncate(size);
+        } else {
+            let data = slice(bytes, pos, size, "truncated TGA data")?;
+            raw.extend_from_slice(data);
+        }
+
+        // TGA stores rows bottom to top unless bit 5 of the descriptor is set
+        let top_down = descriptor & 0x20 != 0;
+        let mut pixels = vec![Vec3::zeros(); count];
+        for (i, pixel) in raw.chunks(bytes_per_pixel).enumerate() {
+            let (x, y) = (i % width, i / width);
+            let row = if top_down { y } else { height - 1 - y };
+            pixels[row * width + x] = match bytes_per_pixel {
+                1 => Vec3::repeat(pixel[0] as f32 / 255.0),
+                _ => Vec3::new(pixel[2] as f32, pixel[1] as f32, pixel[0] as f32) / 255.0,
+            };
+        }
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    // Any PNG color type and bit depth, expanded to 8-bit. Alpha is dropped.
+    pub fn from_png(bytes: &[u8]) -> Result<Self> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|err| invalid(&err.to_string()))?;
+
+        let mut raw = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut raw).map_err(|err| invalid(&err.to_string()))?;
+        let (width, height) = (info.width as usize, info.height as usize);
+        let channels = info.color_type.samples();
+
+        let pixels = raw[..info.buffer_size()]
+            .chunks(info.line_size)
+            .flat_map(|row| row[..width * channels].chunks(channels))
+            .map(|pixel| match channels {
+                1 | 2 => Vec3::repeat(pixel[0] as f32 / 255.0),
+                _ => Vec3::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32) / 255.0,
+            })
+            .collect();
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    // Image files store sRGB encoded values, lighting and filtering want linear ones
+    pub fn into_linear(mut self) -> Self {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = srgb_to_linear(*pixel);
+        }
+        self
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    // Bilinear lookup with u wrapping around and v clamped at the edges
+    pub fn sample_bilinear(&self, u: f32, v: f32) -> Vec3 {
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+
+        let wrap_x = |x: f32| (x as i64).rem_euclid(self.width as i64) as usize;
+        let (x0, x1) = (wrap_x(x0), wrap_x(x0 + 1.0));
+        let y0 = y0 as usize;
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let top = self.pixel(x0, y0) * (1.0 - tx) + self.pixel(x1, y0) * tx;
+        let bottom = self.pixel(x0, y1) * (1.0 - tx) + self.pixel(x1, y1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Uncompressed 24-bit TGA header, rows top to bottom
+    fn tga_header(width: u16, height: u16) -> Vec<u8> {
+        let mut header = vec![0; 18];
+        header[2] = 2;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = 24;
+        header[17] = 0x20;
+        header
+    }
+
+    #[test]
+    fn valid_files_decode_to_their_pixels() {
+        let ppm = Image::from_ppm(b"P6\n2 1\n255\n\xff\x00\x00\x00\x00\xff").unwrap();
+        assert_eq!((ppm.width, ppm.height), (2, 1));
+        assert_eq!(ppm.pixels, vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)]);
+
+        // TGA stores blue, green, red
+        let mut tga = tga_header(1, 2);
+        tga.extend_from_slice(&[0, 0, 255, 255, 0, 0]);
+        let tga = Image::from_tga(&tga).unwrap();
+        assert_eq!(tga.pixels, vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)]);
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        assert!(Image::from_ppm(b"P6\n2 2\n255\n\xff\x00\x00").is_err());
+        assert!(Image::from_ppm(b"P3\n1 1\n255\n0 0").is_err());
+        assert!(Image::from_ppm(b"P6\n2").is_err());
+
+        let mut tga = tga_header(2, 2);
+        tga.extend_from_slice(&[0, 0, 255]);
+        assert!(Image::from_tga(&tga).is_err());
+        assert!(Image::from_tga(&tga[..10]).is_err());
+    }
+
+    #[test]
+    fn zero_size_headers_are_rejected() {
+        assert!(Image::from_ppm(b"P6\n0 4\n255\n").is_err());
+        assert!(Image::from_ppm(b"P3\n4 0\n255\n").is_err());
+        assert!(Image::from_tga(&tga_header(0, 4)).is_err());
+        assert!(Image::from_tga(&tga_header(4, 0)).is_err());
+    }
+}
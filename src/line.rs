@@ -1,12 +1,61 @@
+use nalgebra_glm::Vec3;
+use crate::color::{Color, srgb_to_linear};
 use crate::fragment::Fragment;
+use crate::framebuffer::Rect;
 use crate::vertex::Vertex;
-use crate::color::Color;
 
-pub fn line(a: &Vertex, b: &Vertex) -> Vec<Fragment> {
-    let mut fragments = Vec::new();
+#[derive(Clone, Copy)]
+pub struct LineStyle {
+    // Width in pixels, measured across the line
+    pub thickness: f32,
+    // Xiaolin Wu style coverage: pixels partly covered by the line get
+    // a fractional alpha instead of being in or out
+    pub antialiased: bool,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        LineStyle {
+            thickness: 1.0,
+            antialiased: false,
+        }
+    }
+}
+
+fn linear_color(color: Color) -> Vec3 {
+    srgb_to_linear(color.to_vec3())
+}
+
+// Range of t over which the segment from `start` to `end` lies inside `clip`
+// grown by `margin` on every side, or None if it misses it or isn't finite
+fn clip_segment(start: Vec3, end: Vec3, clip: &Rect, margin: f32) -> Option<(f32, f32)> {
+    if ![start.x, start.y, end.x, end.y].iter().all(|c| c.is_finite()) {
+        return None;
+    }
+    let (min_x, min_y) = (clip.x as f32 - margin, clip.y as f32 - margin);
+    let (max_x, max_y) = ((clip.x + clip.width) as f32 + margin, (clip.y + clip.height) as f32 + margin);
+    let delta = end - start;
+
+    // Liang-Barsky: each side cuts the range where the segment is outside it
+    let mut range = (0.0f32, 1.0f32);
+    for (p, q) in [(-delta.x, start.x - min_x), (delta.x, max_x - start.x), (-delta.y, start.y - min_y), (delta.y, max_y - start.y)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            range.0 = range.0.max(q / p);
+        } else {
+            range.1 = range.1.min(q / p);
+        }
+    }
+    (range.0 <= range.1).then_some(range)
+}
 
-    let start = a.transformed_position;
-    let end = b.transformed_position;
+// One pixel wide Bresenham line. Depth and color are interpolated by progress
+// along the major axis, which is well defined for vertical lines too.
+fn line(start: Vec3, end: Vec3, start_color: Vec3, end_color: Vec3) -> Vec<Fragment> {
+    let mut fragments = Vec::new();
 
     let mut x0 = start.x as i32;
     let mut y0 = start.y as i32;
@@ -21,11 +70,17 @@ pub fn line(a: &Vertex, b: &Vertex) -> Vec<Fragment> {
 
     let mut err = if dx > dy { dx / 2 } else { -dy / 2 };
 
+    let steps = dx.max(dy).max(1) as f32;
+    let mut step = 0;
+
     loop {
-        let z = start.z + (end.z - start.z) * (x0 - start.x as i32) as f32 / (end.x - start.x) as f32;
-        fragments.push(Fragment::new(x0 as f32, y0 as f32, Color::new(255, 255, 255), z));
+        let t = step as f32 / steps;
+        let z = start.z + (end.z - start.z) * t;
+        let color = start_color + (end_color - start_color) * t;
+        fragments.push(Fragment::new(x0 as f32, y0 as f32, color, z));
 
         if x0 == x1 && y0 == y1 { break; }
+        step += 1;
 
         let e2 = err;
         if e2 > -dx {
@@ -40,3 +95,158 @@ pub fn line(a: &Vertex, b: &Vertex) -> Vec<Fragment> {
 
     fragments
 }
+
+// Line of any thickness, optionally antialiased. Walks one pixel at a time
+// along the major axis and covers a span across it; with antialiasing each
+// pixel's alpha is how much of it the span overlaps, which for a one pixel
+// line gives Wu's split between the two nearest pixels. The segment is first
+// cut down to the part that can reach `clip`, so off screen stretches of a
+// line cost nothing.
+pub fn line_with_style(a: &Vertex, b: &Vertex, style: &LineStyle, clip: &Rect) -> Vec<Fragment> {
+    let (a_position, b_position) = (a.transformed_position, b.transformed_position);
+    let (a_color, b_color) = (linear_color(a.color), linear_color(b.color));
+    // Half the span across a line is at most `thickness / sqrt(2)`
+    let margin = style.thickness.max(1.0) + 1.0;
+    let Some((t0, t1)) = clip_segment(a_position, b_position, clip, margin) else {
+        return Vec::new();
+    };
+    let mut start = a_position + (b_position - a_position) * t0;
+    let mut end = a_position + (b_position - a_position) * t1;
+    let mut start_color = a_color + (b_color - a_color) * t0;
+    let mut end_color = a_color + (b_color - a_color) * t1;
+
+    if style.thickness <= 1.0 && !style.antialiased {
+        return line(start, end, start_color, end_color);
+    }
+
+    let mut fragments = Vec::new();
+
+    // Work in (major, minor) coordinates so one loop handles both orientations
+    let steep = (end.y - start.y).abs() > (end.x - start.x).abs();
+    if steep {
+        start = Vec3::new(start.y, start.x, start.z);
+        end = Vec3::new(end.y, end.x, end.z);
+    }
+    if start.x > end.x {
+        std::mem::swap(&mut start, &mut end);
+        std::mem::swap(&mut start_color, &mut end_color);
+    }
+
+    let length = end.x - start.x;
+    let gradient = if length > 0.0 { (end.y - start.y) / length } else { 0.0 };
+    // Vertical extent of the span that gives `thickness` measured across the line
+    let half_span = style.thickness.max(1.0) * 0.5 * (1.0 + gradient * gradient).sqrt();
+
+    let first = start.x.floor() as i32;
+    let last = end.x.floor() as i32;
+
+    for major in first..=last {
+        // Evaluate at the pixel center, clamped to the segment
+        let t = if length > 0.0 {
+            ((major as f32 + 0.5 - start.x) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let center = start.y + (end.y - start.y) * t;
+        let z = start.z + (end.z - start.z) * t;
+        let color = start_color + (end_color - start_color) * t;
+
+        let low = center - half_span;
+        let high = center + half_span;
+
+        for minor in low.floor() as i32..=high.floor() as i32 {
+            let alpha = if style.antialiased {
+                // Overlap of the span with this pixel
+                (high.min(minor as f32 + 1.0) - low.max(minor as f32)).clamp(0.0, 1.0)
+            } else {
+                // Whole pixels whose center is inside the span
+                let pixel_center = minor as f32 + 0.5;
+                if pixel_center >= low && pixel_center < high { 1.0 } else { 0.0 }
+            };
+            if alpha <= 0.0 {
+                continue;
+            }
+
+            let (x, y) = if steep { (minor, major) } else { (major, minor) };
+            fragments.push(Fragment::with_alpha(x as f32, y as f32, color, z, alpha));
+        }
+    }
+
+    fragments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::Vec2;
+
+    fn vertex(x: f32, y: f32, z: f32, color: Color) -> Vertex {
+        let mut vertex = Vertex::new(Vec3::new(x, y, z), Vec3::new(0.0, 0.0, -1.0), Vec2::new(0.0, 0.0));
+        vertex.color = color;
+        vertex
+    }
+
+    #[test]
+    fn vertical_line_interpolates_depth_and_color() {
+        let fragments = line_with_style(
+            &vertex(5.0, 0.0, 0.0, Color::black()),
+            &vertex(5.0, 10.0, 10.0, Color::from_hex(0xFFFFFF)),
+            &LineStyle::default(),
+            &Rect::new(0, 0, 20, 20),
+        );
+
+        assert_eq!(fragments.len(), 11);
+        for (i, fragment) in fragments.iter().enumerate() {
+            assert_eq!(fragment.position, Vec2::new(5.0, i as f32));
+            assert!((fragment.depth - i as f32).abs() < 1e-5);
+        }
+        assert_eq!(fragments[0].color, Vec3::zeros());
+        assert!((fragments[10].color - Vec3::repeat(1.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn antialiased_line_coverage_adds_up_to_its_thickness() {
+        let black = Color::black();
+        for thickness in [1.0, 3.0] {
+            let style = LineStyle { thickness, antialiased: true };
+            let fragments = line_with_style(&vertex(0.0, 2.3, 0.0, black), &vertex(40.0, 2.3, 0.0, black), &style, &Rect::new(0, 0, 40, 40));
+
+            for x in 0..40 {
+                let total: f32 = fragments.iter()
+                    .filter(|f| f.position.x == x as f32)
+                    .map(|f| f.alpha)
+                    .sum();
+                assert!((total - thickness).abs() < 1e-4, "column {} has coverage {}", x, total);
+            }
+        }
+    }
+
+    #[test]
+    fn steep_thick_line_spans_across_x() {
+        let black = Color::black();
+        let style = LineStyle { thickness: 3.0, antialiased: false };
+        let fragments = line_with_style(&vertex(10.5, 0.0, 0.0, black), &vertex(10.5, 20.0, 0.0, black), &style, &Rect::new(0, 0, 40, 40));
+
+        for fragment in &fragments {
+            assert!((9.0..=11.0).contains(&fragment.position.x));
+        }
+        assert_eq!(fragments.iter().filter(|f| f.position.y == 5.0).count(), 3);
+    }
+
+    #[test]
+    fn lines_are_clipped_before_stepping() {
+        let black = Color::black();
+        let clip = Rect::new(0, 0, 20, 20);
+        for style in [LineStyle::default(), LineStyle { thickness: 3.0, antialiased: true }] {
+            // Would take millions of steps if walked end to end
+            let fragments = line_with_style(&vertex(-1e6, 10.5, 0.0, black), &vertex(1e6, 10.5, 0.0, black), &style, &clip);
+            assert!(fragments.len() < 200);
+            assert!(fragments.iter().any(|f| f.position == Vec2::new(10.0, 10.0)));
+            for fragment in &fragments {
+                assert!(fragment.position.x >= -5.0 && fragment.position.x <= 25.0);
+            }
+
+            assert!(line_with_style(&vertex(-50.0, -50.0, 0.0, black), &vertex(-10.0, 40.0, 0.0, black), &style, &clip).is_empty());
+        }
+    }
+}
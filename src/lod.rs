@@ -0,0 +1,126 @@
+use nalgebra_glm::{Mat4, Vec4};
+use crate::sphere::SphereKind;
+use crate::vertex::Vertex;
+
+// Detail of each level, as `generate_sphere` segments, coarsest first
+const LEVEL_SEGMENTS: [u32; 4] = [6, 12, 25, 50];
+// Largest gap, in pixels, allowed between a level's outline and a true circle
+const MAX_SILHOUETTE_ERROR: f32 = 0.5;
+// Fraction the screen radius has to go past a level's range before the level
+// changes, so a body hovering around a boundary doesn't flicker between two
+const HYSTERESIS: f32 = 0.15;
+
+const KINDS: [SphereKind; 3] = [SphereKind::Uv, SphereKind::Icosphere, SphereKind::CubeSphere];
+
+// Sphere meshes of one radius at every detail level, for every SphereKind,
+// shared by all the bodies drawn with them
+pub struct SphereLods {
+    radius: f32,
+    // `levels[kind][level]`, in the order of KINDS
+    levels: Vec<Vec<Vec<Vertex>>>,
+    // Largest screen radius each mesh can be drawn at, indexed the same way
+    limits: Vec<Vec<f32>>,
+}
+
+impl SphereLods {
+    pub fn new(radius: f32) -> Self {
+        let levels: Vec<Vec<Vec<Vertex>>> = KINDS.iter()
+            .map(|kind| LEVEL_SEGMENTS.iter().map(|&segments| kind.generate(radius, segments)).collect())
+            .collect();
+        let limits = levels.iter()
+            .map(|meshes| meshes.iter().map(|mesh| limit(widest_edge_angle(mesh))).collect())
+            .collect();
+        SphereLods { radius, levels, limits }
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    pub fn mesh(&self, kind: SphereKind, level: usize) -> &[Vertex] {
+        &self.levels[kind_index(kind)][level.min(LEVEL_SEGMENTS.len() - 1)]
+    }
+
+    // Level of `kind` for a sphere `screen_radius` pixels wide that was drawn
+    // at `current` last frame. The finest level is used for anything too big
+    // for the others.
+    pub fn select_level(&self, kind: SphereKind, current: usize, screen_radius: f32) -> usize {
+        let limits = &self.limits[kind_index(kind)];
+        let last = limits.len() - 1;
+
+        let mut level = current.min(last);
+        while level < last && screen_radius > limits[level] * (1.0 + HYSTERESIS) {
+            level += 1;
+        }
+        while level > 0 && screen_radius < limits[level - 1] * (1.0 - HYSTERESIS) {
+            level -= 1;
+        }
+        level
+    }
+}
+
+fn kind_index(kind: SphereKind) -> usize {
+    KINDS.iter().position(|k| *k == kind).unwrap_or(0)
+}
+
+// Radius in pixels of a sphere of `radius` drawn with these matrices. The
+// model and view matrices only rotate, scale uniformly and translate.
+pub fn screen_radius(model_matrix: &Mat4, view_matrix: &Mat4, radius: f32) -> f32 {
+    let axis = view_matrix * model_matrix * Vec4::new(1.0, 0.0, 0.0, 0.0);
+    axis.xyz().norm() * radius
+}
+
+// Widest angle, seen from the sphere's center, spanned by an edge of the mesh
+fn widest_edge_angle(mesh: &[Vertex]) -> f32 {
+    mesh.chunks_exact(3)
+        .flat_map(|triangle| [(&triangle[0], &triangle[1]), (&triangle[1], &triangle[2]), (&triangle[2], &triangle[0])])
+        .map(|(a, b)| a.position.normalize().dot(&b.position.normalize()).clamp(-1.0, 1.0).acos())
+        .fold(0.0, f32::max)
+}
+
+// Largest screen radius a mesh whose edges span up to `edge_angle` can be
+// drawn at. Such an edge on a circle of radius r strays r * (1 - cos(angle / 2))
+// inside it at its middle.
+fn limit(edge_angle: f32) -> f32 {
+    MAX_SILHOUETTE_ERROR / (1.0 - (edge_angle * 0.5).cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_follow_screen_size_without_flickering_at_a_boundary() {
+        let spheres = SphereLods::new(1.0);
+        let last = LEVEL_SEGMENTS.len() - 1;
+        for kind in KINDS {
+            // Small bodies get the coarsest mesh, big ones the finest
+            assert_eq!(spheres.select_level(kind, 0, 3.0), 0);
+            assert_eq!(spheres.select_level(kind, 0, 1000.0), last);
+            assert_eq!(spheres.select_level(kind, last, 1.0), 0);
+
+            // Wobbling around the end of level 1's range keeps whichever level it had
+            let boundary = spheres.limits[kind_index(kind)][1];
+            for radius in [boundary * 0.95, boundary * 1.05, boundary * 0.95] {
+                assert_eq!(spheres.select_level(kind, 1, radius), 1);
+                assert_eq!(spheres.select_level(kind, 2, radius), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn limits_come_from_each_kind_of_mesh() {
+        let spheres = SphereLods::new(1.0);
+        // The UV sphere's equator edges span 2 pi / n, and the diagonals
+        // across its quads more, so it can't go past the equator's limit
+        let uv = &spheres.limits[kind_index(SphereKind::Uv)];
+        let equator = 0.5 / (1.0 - (std::f32::consts::PI / LEVEL_SEGMENTS[2] as f32).cos());
+        assert!(uv[2] <= equator * 1.001);
+
+        for limits in &spheres.limits {
+            assert!(limits.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        // Kinds of the same triangle count don't share limits
+        assert_ne!(spheres.limits[kind_index(SphereKind::Icosphere)][2], uv[2]);
+    }
+}
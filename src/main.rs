@@ -1,5 +1,6 @@
-use nalgebra_glm::{Vec3, Mat4};
+use nalgebra_glm::{Vec3, Vec4, Mat4};
 use minifb::{Key, Window, WindowOptions};
+use std::path::Path;
 use std::time::Duration;
 
 mod framebuffer;
@@ -12,19 +13,185 @@ mod fragment;
 mod shaders;
 mod sphere;
 mod fragment_shaders;
-
-use framebuffer::Framebuffer;
+mod text;
+mod hud;
+mod camera;
+mod starfield;
+mod image;
+mod environment;
+mod bloom;
+mod tonemap;
+mod antialiasing;
+mod fxaa;
+mod debug_view;
+mod orbit;
+mod texture;
+mod material;
+mod model;
+mod assets;
+mod normals;
+mod export;
+mod lod;
+mod rings;
+mod shadow;
+mod eclipse;
+
+use framebuffer::{Framebuffer, Rect};
 use vertex::Vertex;
-use triangle::triangle_with_shader;
+use triangle::{triangle_with_shader, wireframe_triangle};
 use shaders::vertex_shader;
-use sphere::{generate_sphere, generate_ring};
-use fragment_shaders::{star_shader, rocky_planet_shader, gas_giant_shader, moon_shader, ring_shader};
+use sphere::SphereKind;
+use lod::{SphereLods, screen_radius};
+use rings::{RingSystem, generate_ring};
+use shadow::{ShadowLight, ShadowMap, ShadowMode};
+use eclipse::{Eclipses, Occluder};
+use fragment_shaders::{FragmentShader, star_shader, rocky_planet_shader, gas_giant_shader, moon_shader, ring_shader, textured_shader, material_shader};
+use hud::Hud;
+use camera::Camera;
+use starfield::Starfield;
+use environment::{Environment, CubeMap, Panorama};
+use bloom::{apply_bloom, BloomSettings};
+use tonemap::{ToneMapSettings, ToneMapping};
+use antialiasing::AntiAliasing;
+use fxaa::apply_fxaa;
+use debug_view::{DebugView, WIREFRAME_COLOR, WIREFRAME_DEPTH_BIAS, WIREFRAME_STYLE};
+use fragment::Fragment;
+use orbit::{Orbit, Trail, orbit_path_fragments, trail_fragments};
+use texture::Texture;
+use material::Material;
+use model::{Model, find_models};
+use assets::AssetResolver;
+use obj::{Handedness, LoadOptions, UpAxis, UvOrigin};
+use export::{ExportMesh, bake_colors, export_scene};
+
+
+pub struct Uniforms<'a> {
+    model_matrix: Mat4,
+    view_matrix: Mat4,
+    // Multiplier applied to the shaded color, above 1.0 for light sources
+    emission: f32,
+    texture: Option<&'a Texture>,
+    material: Option<&'a Material>,
+    // Unit direction towards the light, in view space like the normals
+    light_dir: Vec3,
+    // Ring system around the body, for the rings' own shading and the
+    // shadow they cast on the planet
+    rings: Option<&'a RingSystem>,
+    // Depth of everything casting shadows, seen from the light
+    shadow_map: Option<&'a ShadowMap>,
+    // Spheres that can eclipse the light, for shadows without a shadow map
+    eclipses: Option<&'a Eclipses>,
+}
 
+impl Uniforms<'_> {
+    fn new(model_matrix: Mat4, view_matrix: Mat4) -> Self {
+        Uniforms {
+            model_matrix,
+            view_matrix,
+            emission: 1.0,
+            texture: None,
+            material: None,
+            light_dir: view_direction(&view_matrix, SUN_DIRECTION),
+            rings: None,
+            shadow_map: None,
+            eclipses: None,
+        }
+    }
+}
 
-pub struct Uniforms {
-    model_matrix: Mat4,
+// One call to `render`: a mesh with the shader and uniforms to draw it with
+struct Draw<'a> {
+    name: &'a str,
+    vertices: &'a [Vertex],
+    shader: FragmentShader,
+    uniforms: Uniforms<'a>,
+    // Whether the mesh goes into the shadow map. Lights don't, and neither do
+    // the rings, whose shadow comes from their density profile.
+    casts_shadow: bool,
+    // The sphere it eclipses the light with, for bodies drawn as spheres
+    occluder: Option<Occluder>,
+}
+
+impl<'a> Draw<'a> {
+    // A body drawn as a sphere of `radius` in model space. Emissive bodies are
+    // the lights, which neither cast shadows nor eclipse anything.
+    fn body(name: &'a str, vertices: &'a [Vertex], shader: FragmentShader, uniforms: Uniforms<'a>, radius: f32) -> Self {
+        let is_light = uniforms.emission > 1.0;
+        let occluder = (!is_light).then(|| Occluder::from_model(&uniforms.model_matrix, radius));
+        Draw { name, vertices, shader, uniforms, casts_shadow: !is_light, occluder }
+    }
+}
+
+// How a body's sphere is drawn: the kind of mesh, and the detail level it
+// was last drawn at
+struct BodySphere {
+    kind: SphereKind,
+    level: usize,
 }
 
+impl BodySphere {
+    fn new() -> Self {
+        BodySphere { kind: SphereKind::Icosphere, level: 0 }
+    }
+
+    fn name(&self) -> String {
+        format!("{} LOD {}", self.kind.name(), self.level)
+    }
+
+    // Mesh to draw the body with `uniforms`, at the detail its size on screen needs
+    fn mesh<'a>(&mut self, spheres: &'a SphereLods, uniforms: &Uniforms) -> &'a [Vertex] {
+        let radius = screen_radius(&uniforms.model_matrix, &uniforms.view_matrix, spheres.radius());
+        self.level = spheres.select_level(self.kind, self.level, radius);
+        spheres.mesh(self.kind, self.level)
+    }
+}
+
+// The sphere of every body drawn as one
+struct BodySpheres {
+    star: BodySphere,
+    planet: BodySphere,
+    gas_giant: BodySphere,
+    moon: BodySphere,
+}
+
+impl BodySpheres {
+    fn new() -> Self {
+        BodySpheres {
+            star: BodySphere::new(),
+            planet: BodySphere::new(),
+            gas_giant: BodySphere::new(),
+            moon: BodySphere::new(),
+        }
+    }
+
+    fn get(&mut self, body: Body) -> Option<&mut BodySphere> {
+        match body {
+            Body::Sun => Some(&mut self.star),
+            Body::Earth => Some(&mut self.planet),
+            Body::Moon => Some(&mut self.moon),
+            Body::Jupiter => Some(&mut self.gas_giant),
+            Body::Rings | Body::Ship => None,
+        }
+    }
+}
+
+const STAR_EMISSION: f32 = 3.0;
+// Frames of history kept in each orbit trail
+const ORBIT_TRAIL_LENGTH: usize = 90;
+const MODELS_DIRECTORY: &str = "assets/models";
+// Size of a loaded model in MODEL mode, as the half width of its bounding box
+const MODEL_SIZE: f32 = 60.0;
+const EXPORT_DIRECTORY: &str = "exports";
+// Direction towards the light in modes without a star, up and to the left
+// of the viewer
+const SUN_DIRECTION: Vec3 = Vec3::new(-0.8, -0.3, -0.5);
+// Angle from the sun's center to its edge, in radians, for analytic eclipses.
+// Bigger than the real sun's so the penumbra can be seen.
+const SUN_ANGULAR_RADIUS: f32 = 0.05;
+// Rotation giving the gas giant's spin axis its tilt, shared by its rings so
+// they stay over its equator
+const GAS_GIANT_TILT: Vec3 = Vec3::new(-0.4, 0.0, 0.5);
+
 fn create_model_matrix(translation: Vec3, scale: f32, rotation: Vec3) -> Mat4 {
     let (sin_x, cos_x) = rotation.x.sin_cos();
     let (sin_y, cos_y) = rotation.y.sin_cos();
@@ -63,7 +230,44 @@ fn create_model_matrix(translation: Vec3, scale: f32, rotation: Vec3) -> Mat4 {
     transform_matrix * rotation_matrix
 }
 
-fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Vertex], fragment_shader: fragment_shaders::FragmentShader) {
+// Model matrix for a body turned by `spin` around its own y axis, after
+// that axis is tilted by the `tilt` rotation
+fn create_spinning_matrix(translation: Vec3, scale: f32, tilt: Vec3, spin: f32) -> Mat4 {
+    create_model_matrix(translation, scale, tilt) * create_model_matrix(Vec3::zeros(), 1.0, Vec3::new(0.0, spin, 0.0))
+}
+
+// World space `direction` as a unit vector in view space
+fn view_direction(view_matrix: &Mat4, direction: Vec3) -> Vec3 {
+    (view_matrix * Vec4::new(direction.x, direction.y, direction.z, 0.0)).xyz().normalize()
+}
+
+// Value following a command line flag, or None with a warning if the
+// arguments end first
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Option<String> {
+    let value = args.next();
+    if value.is_none() {
+        eprintln!("Missing value for {}", flag);
+    }
+    value
+}
+
+// Writes the meshes of a frame, placed in the scene and with their shading
+// baked into vertex colors, to EXPORT_DIRECTORY
+fn export_draws(draws: &[Draw], name: &str) {
+    let meshes: Vec<ExportMesh> = draws.iter().map(|draw| ExportMesh {
+        name: draw.name,
+        vertices: draw.vertices,
+        model_matrix: draw.uniforms.model_matrix,
+        colors: bake_colors(draw.vertices, draw.shader, &draw.uniforms),
+    }).collect();
+
+    match export_scene(Path::new(EXPORT_DIRECTORY), name, &meshes) {
+        Ok((obj, ply)) => println!("Exported {} and {}", obj.display(), ply.display()),
+        Err(err) => eprintln!("Could not export the scene: {}", err),
+    }
+}
+
+fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Vertex], fragment_shader: FragmentShader, debug_view: DebugView) {
     // Vertex Shader Stage
     let mut transformed_vertices = Vec::with_capacity(vertex_array.len());
     for vertex in vertex_array {
@@ -84,31 +288,125 @@ fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Ve
     }
 
     // Rasterization Stage
+    // Fragments only come from inside the clip rect, so they can be written directly
+    let clip = framebuffer.clip_rect();
+    let fragment_shader = debug_view.fragment_shader().unwrap_or(fragment_shader);
     let mut fragments = Vec::new();
     for tri in &triangles {
-        fragments.extend(triangle_with_shader(&tri[0], &tri[1], &tri[2], fragment_shader));
+        fragments.extend(triangle_with_shader(&tri[0], &tri[1], &tri[2], fragment_shader, uniforms, framebuffer.sample_positions(), &clip));
     }
 
     // Fragment Processing Stage
+    let emission = if debug_view.is_shaded() { uniforms.emission } else { 1.0 };
     for fragment in fragments {
         let x = fragment.position.x as usize;
         let y = fragment.position.y as usize;
-        if x < framebuffer.width && y < framebuffer.height {
-            let color = fragment.color.to_hex();
-            framebuffer.set_current_color(color);
-            framebuffer.point(x, y, fragment.depth);
+        if debug_view == DebugView::Overdraw {
+            framebuffer.add_light(x, y, Vec3::new(1.0, 1.0, 1.0));
+            continue;
+        }
+        let color = fragment.color * emission;
+        framebuffer.set_current_color(color);
+        framebuffer.point_coverage(x, y, fragment.coverage, &fragment.sample_depths);
+    }
+
+    if debug_view == DebugView::Wireframe {
+        let clip = framebuffer.clip_rect();
+        for tri in &triangles {
+            let mut edges = wireframe_triangle(&tri[0], &tri[1], &tri[2], &WIREFRAME_STYLE, &clip);
+            for fragment in edges.iter_mut() {
+                fragment.color = WIREFRAME_COLOR;
+            }
+            draw_line_fragments(framebuffer, &edges, WIREFRAME_DEPTH_BIAS);
         }
     }
 }
 
-#[derive(Clone, Copy)]
+// Depth tests line fragments and blends them in by their alpha. `depth_bias`
+// moves them towards the camera, for lines drawn on top of a surface.
+fn draw_line_fragments(framebuffer: &mut Framebuffer, fragments: &[Fragment], depth_bias: f32) {
+    for fragment in fragments {
+        if fragment.position.x < 0.0 || fragment.position.y < 0.0 {
+            continue;
+        }
+        let x = fragment.position.x as usize;
+        let y = fragment.position.y as usize;
+        framebuffer.set_current_color(fragment.color);
+        framebuffer.blend_point(x, y, fragment.depth - depth_bias, fragment.alpha);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 enum ShaderMode {
     Star,
     RockyPlanet,
     GasGiant,
+    // Rocky planet with an OBJ model flying around it
+    Model,
     All,
 }
 
+// Something TAB can select
+#[derive(Clone, Copy, PartialEq)]
+enum Body {
+    Sun,
+    Earth,
+    Moon,
+    Jupiter,
+    Rings,
+    Ship,
+}
+
+impl Body {
+    fn name(&self) -> &'static str {
+        match self {
+            Body::Sun => "SUN",
+            Body::Earth => "EARTH",
+            Body::Moon => "MOON",
+            Body::Jupiter => "JUPITER",
+            Body::Rings => "RINGS",
+            Body::Ship => "SHIP",
+        }
+    }
+}
+
+impl ShaderMode {
+    fn name(&self) -> &'static str {
+        match self {
+            ShaderMode::Star => "STAR",
+            ShaderMode::RockyPlanet => "ROCKY PLANET",
+            ShaderMode::GasGiant => "GAS GIANT",
+            ShaderMode::Model => "MODEL",
+            ShaderMode::All => "ALL",
+        }
+    }
+
+    // Bodies visible in this mode, in the order TAB cycles through them
+    fn bodies(&self) -> &'static [Body] {
+        match self {
+            ShaderMode::Star => &[Body::Sun],
+            ShaderMode::RockyPlanet => &[Body::Earth, Body::Moon],
+            ShaderMode::GasGiant => &[Body::Jupiter, Body::Rings],
+            ShaderMode::Model => &[Body::Earth, Body::Ship],
+            ShaderMode::All => &[Body::Sun, Body::Earth, Body::Moon, Body::Jupiter, Body::Rings],
+        }
+    }
+
+    // Orbits of the bodies circling another one in this mode
+    fn orbits(&self) -> Vec<Orbit> {
+        match self {
+            ShaderMode::RockyPlanet => vec![Orbit::circular(Vec3::new(600.0, 400.0, 0.0), 200.0, 0.8)],
+            ShaderMode::All => vec![Orbit::circular(Vec3::new(600.0, 400.0, 0.0), 150.0, 0.8)],
+            // Tilted steeply so the model passes in front of and behind the planet
+            ShaderMode::Model => vec![Orbit {
+                inclination: 1.25,
+                ..Orbit::circular(Vec3::new(600.0, 400.0, 0.0), 260.0, 0.5)
+            }],
+            ShaderMode::Star | ShaderMode::GasGiant => Vec::new(),
+        }
+    }
+}
+
 fn main() {
     let window_width = 1200;
     let window_height = 800;
@@ -130,20 +428,123 @@ fn main() {
 
     framebuffer.set_background_color(0x000011);
 
-    // Generate spheres
-    let sphere_segments = 50;
-    let star_sphere = generate_sphere(1.0, sphere_segments);
-    let planet_sphere = generate_sphere(1.0, sphere_segments);
-    let gas_giant_sphere = generate_sphere(1.0, sphere_segments);
-    let moon_sphere = generate_sphere(0.3, 30);
-    let ring = generate_ring(1.2, 2.0, 60);
-
-    let mut shader_mode = ShaderMode::All;
+    let mut environments = vec![
+        Environment::Starfield(Starfield::new(0x5EED, 3000)),
+        Environment::SolidColor(0x000011),
+    ];
+    let mut planet_texture = None;
+    let mut model_paths = find_models(MODELS_DIRECTORY);
+    let mut start_mode = ShaderMode::All;
+    let mut asset_root = None;
+    // Models without normals would otherwise all be lit as if facing up
+    let mut model_options = LoadOptions { generate_normals: true, ..Default::default() };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--skybox" => if let Some(directory) = flag_value(&mut args, &arg) {
+                match CubeMap::load(&directory) {
+                    Ok(cube_map) => environments.push(Environment::CubeMap(cube_map)),
+                    Err(err) => eprintln!("Could not load skybox from {}: {}", directory, err),
+                }
+            },
+            "--panorama" => if let Some(path) = flag_value(&mut args, &arg) {
+                match Panorama::load(&path) {
+                    Ok(panorama) => environments.push(Environment::Panorama(panorama)),
+                    Err(err) => eprintln!("Could not load panorama {}: {}", path, err),
+                }
+            },
+            "--planet-texture" => if let Some(path) = flag_value(&mut args, &arg) {
+                match Texture::load(&path) {
+                    Ok(texture) => planet_texture = Some(texture),
+                    Err(err) => eprintln!("Could not load planet texture {}: {}", path, err),
+                }
+            },
+            "--model" => if let Some(path) = flag_value(&mut args, &arg) {
+                model_paths.insert(0, path.into());
+                start_mode = ShaderMode::Model;
+            },
+            "--asset-root" => if let Some(directory) = flag_value(&mut args, &arg) {
+                asset_root = Some(directory.into());
+            },
+            "--up-axis" => if let Some(axis) = flag_value(&mut args, &arg) {
+                match UpAxis::parse(&axis) {
+                    Some(axis) => model_options.up_axis = axis,
+                    None => eprintln!("Unknown up axis {}, expected y or z", axis),
+                }
+            },
+            "--handedness" => if let Some(handedness) = flag_value(&mut args, &arg) {
+                match Handedness::parse(&handedness) {
+                    Some(handedness) => model_options.handedness = handedness,
+                    None => eprintln!("Unknown handedness {}, expected right or left", handedness),
+                }
+            },
+            "--uv-origin" => if let Some(origin) = flag_value(&mut args, &arg) {
+                match UvOrigin::parse(&origin) {
+                    Some(origin) => model_options.uv_origin = origin,
+                    None => eprintln!("Unknown UV origin {}, expected bottom-left or top-left", origin),
+                }
+            },
+            _ => eprintln!("Ignoring unknown argument: {}", arg),
+        }
+    }
+    let assets = AssetResolver::new(asset_root);
+    // Start on an image background if one was given
+    let mut environment_index = if environments.len() > 2 { environments.len() - 1 } else { 0 };
+
+    let mut camera = Camera::new(Vec3::new(
+        framebuffer_width as f32 / 2.0,
+        framebuffer_height as f32 / 2.0,
+        0.0,
+    ));
+    let camera_speed = 0.02;
+
+    // Sphere meshes at every detail level, shared by the bodies. The moon's
+    // procedural detail is sized for a radius of 0.3.
+    let unit_spheres = SphereLods::new(1.0);
+    let moon_spheres = SphereLods::new(0.3);
+    let mut body_spheres = BodySpheres::new();
+    let ring_system = RingSystem::new(1.2, 2.0).expect("ring radii are in order");
+    let ring = generate_ring(&ring_system, 60);
+    let mut shadow_map = ShadowMap::default();
+    let mut shadow_mode = ShadowMode::Map;
+
+    let mut shader_mode = start_mode;
+    let mut model: Option<Model> = None;
+    let mut model_index = 0;
+    let mut loaded_model_index = None;
     let mut time = 0.0f32;
     let mut prev_key1 = false;
     let mut prev_key2 = false;
     let mut prev_key3 = false;
+    let mut prev_key4 = false;
     let mut prev_key0 = false;
+    let mut prev_keyn = false;
+    let mut prev_keyh = false;
+    let mut prev_keytab = false;
+    let mut prev_keyb = false;
+    let mut prev_keyg = false;
+    let mut bloom = BloomSettings::default();
+    let mut prev_keyt = false;
+    let mut prev_key_minus = false;
+    let mut prev_key_equal = false;
+    let mut tone_mapping = ToneMapSettings::default();
+    let mut prev_keym = false;
+    let mut anti_aliasing = AntiAliasing::Off;
+    let mut prev_keyv = false;
+    let mut debug_view = DebugView::Shaded;
+    let mut prev_keyo = false;
+    let mut show_orbits = true;
+    let mut prev_keyf = false;
+    let mut prev_keyx = false;
+    let mut prev_keys = false;
+    let mut prev_keyl = false;
+    let mut prev_keyc = false;
+    // Drawing limited to the middle of the window, to see primitives clipped
+    let mut scissor = false;
+    let mut trails: Vec<Trail> = Vec::new();
+    let mut trails_mode = None;
+    let mut selected_body = 0;
+    let mut hud = Hud::new();
 
     while window.is_open() {
         if window.is_key_down(Key::Escape) {
@@ -154,7 +555,24 @@ fn main() {
         let key1 = window.is_key_down(Key::Key1);
         let key2 = window.is_key_down(Key::Key2);
         let key3 = window.is_key_down(Key::Key3);
+        let key4 = window.is_key_down(Key::Key4);
         let key0 = window.is_key_down(Key::Key0);
+        let keyn = window.is_key_down(Key::N);
+        let keyh = window.is_key_down(Key::H);
+        let keytab = window.is_key_down(Key::Tab);
+        let keyb = window.is_key_down(Key::B);
+        let keyg = window.is_key_down(Key::G);
+        let keyt = window.is_key_down(Key::T);
+        let key_minus = window.is_key_down(Key::Minus);
+        let key_equal = window.is_key_down(Key::Equal);
+        let keym = window.is_key_down(Key::M);
+        let keyv = window.is_key_down(Key::V);
+        let keyo = window.is_key_down(Key::O);
+        let keyf = window.is_key_down(Key::F);
+        let keyx = window.is_key_down(Key::X);
+        let keys = window.is_key_down(Key::S);
+        let keyl = window.is_key_down(Key::L);
+        let keyc = window.is_key_down(Key::C);
 
         if key1 && !prev_key1 {
             shader_mode = ShaderMode::Star;
@@ -165,18 +583,152 @@ fn main() {
         if key3 && !prev_key3 {
             shader_mode = ShaderMode::GasGiant;
         }
+        if key4 && !prev_key4 {
+            shader_mode = ShaderMode::Model;
+        }
         if key0 && !prev_key0 {
             shader_mode = ShaderMode::All;
         }
+        if keyn && !prev_keyn && !model_paths.is_empty() {
+            model_index = (model_index + 1) % model_paths.len();
+        }
+
+        if keyh && !prev_keyh {
+            hud.toggle();
+        }
+        if keytab && !prev_keytab {
+            selected_body += 1;
+        }
+        if keyb && !prev_keyb {
+            environment_index = (environment_index + 1) % environments.len();
+        }
+        if keyg && !prev_keyg {
+            bloom.enabled = !bloom.enabled;
+        }
+        if keyt && !prev_keyt {
+            tone_mapping.operator = tone_mapping.operator.next();
+        }
+        if key_minus && !prev_key_minus {
+            tone_mapping.exposure = (tone_mapping.exposure / 1.25).max(0.05);
+        }
+        if key_equal && !prev_key_equal {
+            tone_mapping.exposure = (tone_mapping.exposure * 1.25).min(20.0);
+        }
+        if keym && !prev_keym {
+            anti_aliasing = anti_aliasing.next();
+            framebuffer.set_sample_positions(anti_aliasing.sample_positions());
+        }
+        if keyv && !prev_keyv {
+            debug_view = debug_view.next();
+        }
+        if keyo && !prev_keyo {
+            show_orbits = !show_orbits;
+        }
+        if keyf && !prev_keyf {
+            if let Some(texture) = planet_texture.as_mut() {
+                texture.filter = texture.filter.next();
+            }
+        }
+        if keyl && !prev_keyl {
+            shadow_mode = shadow_mode.next();
+        }
+        if keyc && !prev_keyc {
+            scissor = !scissor;
+            let (width, height) = (framebuffer.width, framebuffer.height);
+            framebuffer.set_scissor(scissor.then(|| Rect::new(width / 4, height / 4, width / 2, height / 2)));
+        }
+        // Done once the frame's meshes are known
+        let export_requested = keyx && !prev_keyx;
+        let switch_sphere = keys && !prev_keys;
 
         prev_key1 = key1;
         prev_key2 = key2;
         prev_key3 = key3;
+        prev_key4 = key4;
         prev_key0 = key0;
+        prev_keyn = keyn;
+        prev_keyh = keyh;
+        prev_keytab = keytab;
+        prev_keyb = keyb;
+        prev_keyg = keyg;
+        prev_keyt = keyt;
+        prev_key_minus = key_minus;
+        prev_key_equal = key_equal;
+        prev_keym = keym;
+        prev_keyv = keyv;
+        prev_keyo = keyo;
+        prev_keyf = keyf;
+        prev_keyx = keyx;
+        prev_keys = keys;
+        prev_keyl = keyl;
+        prev_keyc = keyc;
+
+        let bodies = shader_mode.bodies();
+        selected_body %= bodies.len();
+
+        // S switches how the selected body's sphere is built
+        if let Some(sphere) = body_spheres.get(bodies[selected_body]) {
+            if switch_sphere {
+                sphere.kind = sphere.kind.next();
+            }
+        }
+
+        // Orbit the camera with the arrow keys
+        if window.is_key_down(Key::Left) {
+            camera.orbit(camera_speed, 0.0);
+        }
+        if window.is_key_down(Key::Right) {
+            camera.orbit(-camera_speed, 0.0);
+        }
+        if window.is_key_down(Key::Up) {
+            camera.orbit(0.0, -camera_speed);
+        }
+        if window.is_key_down(Key::Down) {
+            camera.orbit(0.0, camera_speed);
+        }
+        let view_matrix = camera.view_matrix();
 
-        framebuffer.clear();
+        match debug_view {
+            DebugView::Shaded | DebugView::Wireframe => environments[environment_index].render(&mut framebuffer, &camera),
+            // Overdraw counts fragments by adding them up, so start from zero
+            _ => framebuffer.clear_with(|_, _| Vec3::zeros()),
+        }
         time += 0.01;
 
+        // A texture given on the command line replaces the procedural planet
+        let planet_shader: FragmentShader = if planet_texture.is_some() { textured_shader } else { rocky_planet_shader };
+
+        // Models are loaded the first time they are shown
+        if shader_mode == ShaderMode::Model && loaded_model_index != Some(model_index) && !model_paths.is_empty() {
+            let path = &model_paths[model_index];
+            model = match Model::load(path, &assets, &model_options) {
+                Ok(model) => {
+                    for warning in model.report().warnings() {
+                        eprintln!("Warning: {}: {}", path.display(), warning);
+                    }
+                    Some(model)
+                }
+                Err(err) => {
+                    eprintln!("Could not load model {}: {}", path.display(), err);
+                    None
+                }
+            };
+            loaded_model_index = Some(model_index);
+        }
+
+        let orbits = shader_mode.orbits();
+        if trails_mode != Some(shader_mode) {
+            trails = orbits.iter().map(|_| Trail::new(ORBIT_TRAIL_LENGTH)).collect();
+            trails_mode = Some(shader_mode);
+        }
+        for (trail, orbit) in trails.iter_mut().zip(orbits.iter()) {
+            trail.push(orbit.position(time));
+        }
+
+        let mut draws = Vec::new();
+        // The star lights the bodies in ALL mode, the sun everywhere else
+        let mut shadow_light = ShadowLight::Directional(SUN_DIRECTION);
+        let mut light_size = SUN_ANGULAR_RADIUS;
         match shader_mode {
             ShaderMode::Star => {
                 // Render star in center
@@ -185,8 +737,11 @@ fn main() {
                     150.0,
                     Vec3::new(time * 0.5, time * 0.3, 0.0)
                 );
-                let uniforms = Uniforms { model_matrix };
-                render(&mut framebuffer, &uniforms, &star_sphere, star_shader);
+                let uniforms = Uniforms {
+                    emission: STAR_EMISSION,
+                    ..Uniforms::new(model_matrix, view_matrix)
+                };
+                draws.push(Draw::body("star", body_spheres.star.mesh(&unit_spheres, &uniforms), star_shader, uniforms, unit_spheres.radius()));
             }
             ShaderMode::RockyPlanet => {
                 // Render rocky planet with moon
@@ -195,93 +750,231 @@ fn main() {
                     120.0,
                     Vec3::new(time * 0.3, time * 0.5, 0.0)
                 );
-                let planet_uniforms = Uniforms { model_matrix: planet_matrix };
-                render(&mut framebuffer, &planet_uniforms, &planet_sphere, rocky_planet_shader);
+                let planet_uniforms = Uniforms {
+                    texture: planet_texture.as_ref(),
+                    ..Uniforms::new(planet_matrix, view_matrix)
+                };
+                draws.push(Draw::body("planet", body_spheres.planet.mesh(&unit_spheres, &planet_uniforms), planet_shader, planet_uniforms, unit_spheres.radius()));
 
                 // Render moon orbiting the planet
-                let moon_distance = 200.0;
-                let moon_angle = time * 0.8;
-                let moon_x = 600.0 + moon_distance * moon_angle.cos();
-                let moon_y = 400.0 + moon_distance * moon_angle.sin();
                 let moon_matrix = create_model_matrix(
-                    Vec3::new(moon_x, moon_y, 0.0),
+                    orbits[0].position(time),
                     40.0,
                     Vec3::new(time * 0.4, time * 0.4, 0.0)
                 );
-                let moon_uniforms = Uniforms { model_matrix: moon_matrix };
-                render(&mut framebuffer, &moon_uniforms, &moon_sphere, moon_shader);
+                let moon_uniforms = Uniforms::new(moon_matrix, view_matrix);
+                draws.push(Draw::body("moon", body_spheres.moon.mesh(&moon_spheres, &moon_uniforms), moon_shader, moon_uniforms, moon_spheres.radius()));
             }
             ShaderMode::GasGiant => {
                 // Render gas giant with rings
-                let planet_matrix = create_model_matrix(
+                let planet_matrix = create_spinning_matrix(
                     Vec3::new(600.0, 400.0, 0.0),
                     140.0,
-                    Vec3::new(time * 0.2, time * 0.4, 0.0)
+                    GAS_GIANT_TILT,
+                    time * 0.4
                 );
-                let planet_uniforms = Uniforms { model_matrix: planet_matrix };
-                render(&mut framebuffer, &planet_uniforms, &gas_giant_sphere, gas_giant_shader);
+                let planet_uniforms = Uniforms {
+                    rings: Some(&ring_system),
+                    ..Uniforms::new(planet_matrix, view_matrix)
+                };
+                draws.push(Draw::body("gas giant", body_spheres.gas_giant.mesh(&unit_spheres, &planet_uniforms), gas_giant_shader, planet_uniforms, unit_spheres.radius()));
 
                 // Render rings
-                let ring_matrix = create_model_matrix(
+                let ring_matrix = create_spinning_matrix(
                     Vec3::new(600.0, 400.0, 0.0),
                     140.0,
-                    Vec3::new(0.0, 0.0, time * 0.1)
+                    GAS_GIANT_TILT,
+                    time * 0.05
                 );
-                let ring_uniforms = Uniforms { model_matrix: ring_matrix };
-                render(&mut framebuffer, &ring_uniforms, &ring, ring_shader);
+                let ring_uniforms = Uniforms {
+                    rings: Some(&ring_system),
+                    ..Uniforms::new(ring_matrix, view_matrix)
+                };
+                draws.push(Draw { name: "rings", vertices: &ring, shader: ring_shader, uniforms: ring_uniforms, casts_shadow: false, occluder: None });
+            }
+            ShaderMode::Model => {
+                let planet_matrix = create_model_matrix(
+                    Vec3::new(600.0, 400.0, 0.0),
+                    120.0,
+                    Vec3::new(time * 0.3, time * 0.5, 0.0)
+                );
+                let planet_uniforms = Uniforms {
+                    texture: planet_texture.as_ref(),
+                    ..Uniforms::new(planet_matrix, view_matrix)
+                };
+                draws.push(Draw::body("planet", body_spheres.planet.mesh(&unit_spheres, &planet_uniforms), planet_shader, planet_uniforms, unit_spheres.radius()));
+
+                // Model centered and scaled by its bounding box, spinning as it orbits
+                if let Some(model) = &model {
+                    let model_matrix = create_model_matrix(
+                        orbits[0].position(time),
+                        MODEL_SIZE,
+                        Vec3::new(0.0, time * 0.6, 0.0)
+                    ) * model.fit_matrix;
+
+                    for (vertices, material) in model.meshes() {
+                        let uniforms = Uniforms {
+                            material: Some(material),
+                            ..Uniforms::new(model_matrix, view_matrix)
+                        };
+                        draws.push(Draw { name: &material.name, vertices, shader: material_shader, uniforms, casts_shadow: true, occluder: None });
+                    }
+                }
             }
             ShaderMode::All => {
-                // Render all three planets side by side
+                // Render all three planets side by side, lit by the star
                 // Star (left)
+                let star_center = Vec3::new(250.0, 400.0, 0.0);
                 let star_matrix = create_model_matrix(
-                    Vec3::new(250.0, 400.0, 0.0),
+                    star_center,
                     100.0,
                     Vec3::new(time * 0.5, time * 0.3, 0.0)
                 );
-                let star_uniforms = Uniforms { model_matrix: star_matrix };
-                render(&mut framebuffer, &star_uniforms, &star_sphere, star_shader);
+                let star_uniforms = Uniforms {
+                    emission: STAR_EMISSION,
+                    ..Uniforms::new(star_matrix, view_matrix)
+                };
+                draws.push(Draw::body("star", body_spheres.star.mesh(&unit_spheres, &star_uniforms), star_shader, star_uniforms, unit_spheres.radius()));
+                shadow_light = ShadowLight::Point(star_center);
+                light_size = Occluder::from_model(&star_matrix, unit_spheres.radius()).radius;
+                let towards_star = |center: Vec3| view_direction(&view_matrix, star_center - center);
 
                 // Rocky Planet (center) with moon
+                let planet_center = Vec3::new(600.0, 400.0, 0.0);
                 let planet_matrix = create_model_matrix(
-                    Vec3::new(600.0, 400.0, 0.0),
+                    planet_center,
                     90.0,
                     Vec3::new(time * 0.3, time * 0.5, 0.0)
                 );
-                let planet_uniforms = Uniforms { model_matrix: planet_matrix };
-                render(&mut framebuffer, &planet_uniforms, &planet_sphere, rocky_planet_shader);
+                let planet_uniforms = Uniforms {
+                    texture: planet_texture.as_ref(),
+                    light_dir: towards_star(planet_center),
+                    ..Uniforms::new(planet_matrix, view_matrix)
+                };
+                draws.push(Draw::body("planet", body_spheres.planet.mesh(&unit_spheres, &planet_uniforms), planet_shader, planet_uniforms, unit_spheres.radius()));
 
                 // Moon
-                let moon_distance = 150.0;
-                let moon_angle = time * 0.8;
-                let moon_x = 600.0 + moon_distance * moon_angle.cos();
-                let moon_y = 400.0 + moon_distance * moon_angle.sin();
+                let moon_center = orbits[0].position(time);
                 let moon_matrix = create_model_matrix(
-                    Vec3::new(moon_x, moon_y, 0.0),
+                    moon_center,
                     30.0,
                     Vec3::new(time * 0.4, time * 0.4, 0.0)
                 );
-                let moon_uniforms = Uniforms { model_matrix: moon_matrix };
-                render(&mut framebuffer, &moon_uniforms, &moon_sphere, moon_shader);
+                let moon_uniforms = Uniforms {
+                    light_dir: towards_star(moon_center),
+                    ..Uniforms::new(moon_matrix, view_matrix)
+                };
+                draws.push(Draw::body("moon", body_spheres.moon.mesh(&moon_spheres, &moon_uniforms), moon_shader, moon_uniforms, moon_spheres.radius()));
 
                 // Gas Giant (right) with rings
-                let gas_matrix = create_model_matrix(
-                    Vec3::new(950.0, 400.0, 0.0),
+                let gas_center = Vec3::new(950.0, 400.0, 0.0);
+                let gas_matrix = create_spinning_matrix(
+                    gas_center,
                     110.0,
-                    Vec3::new(time * 0.2, time * 0.4, 0.0)
+                    GAS_GIANT_TILT,
+                    time * 0.4
                 );
-                let gas_uniforms = Uniforms { model_matrix: gas_matrix };
-                render(&mut framebuffer, &gas_uniforms, &gas_giant_sphere, gas_giant_shader);
+                let gas_uniforms = Uniforms {
+                    light_dir: towards_star(gas_center),
+                    rings: Some(&ring_system),
+                    ..Uniforms::new(gas_matrix, view_matrix)
+                };
+                draws.push(Draw::body("gas giant", body_spheres.gas_giant.mesh(&unit_spheres, &gas_uniforms), gas_giant_shader, gas_uniforms, unit_spheres.radius()));
 
                 // Rings for gas giant
-                let ring_matrix = create_model_matrix(
-                    Vec3::new(950.0, 400.0, 0.0),
+                let ring_matrix = create_spinning_matrix(
+                    gas_center,
                     110.0,
-                    Vec3::new(0.0, 0.0, time * 0.1)
+                    GAS_GIANT_TILT,
+                    time * 0.05
                 );
-                let ring_uniforms = Uniforms { model_matrix: ring_matrix };
-                render(&mut framebuffer, &ring_uniforms, &ring, ring_shader);
+                let ring_uniforms = Uniforms {
+                    light_dir: towards_star(gas_center),
+                    rings: Some(&ring_system),
+                    ..Uniforms::new(ring_matrix, view_matrix)
+                };
+                draws.push(Draw { name: "rings", vertices: &ring, shader: ring_shader, uniforms: ring_uniforms, casts_shadow: false, occluder: None });
+            }
+        }
+
+        // Shadows, either from a shadow pass (depth of the casters from the
+        // light, read back by the fragment shaders) or from the spheres of the
+        // bodies tested against the light in the fragment shaders
+        let eclipses = Eclipses::new(shadow_light, light_size, draws.iter().filter_map(|draw| draw.occluder).collect());
+        match shadow_mode {
+            ShadowMode::Map => {
+                let casters: Vec<(&[Vertex], Mat4)> = draws.iter()
+                    .filter(|draw| draw.casts_shadow)
+                    .map(|draw| (draw.vertices, draw.uniforms.model_matrix))
+                    .collect();
+                // Nothing to shadow with in STAR mode
+                if !casters.is_empty() {
+                    shadow_map.render(shadow_light, &casters);
+                    for draw in &mut draws {
+                        draw.uniforms.shadow_map = Some(&shadow_map);
+                    }
+                }
             }
+            ShadowMode::Analytic => {
+                for draw in &mut draws {
+                    draw.uniforms.eclipses = Some(&eclipses);
+                }
+            }
+            ShadowMode::Off => {}
+        }
+
+        for draw in &draws {
+            render(&mut framebuffer, &draw.uniforms, draw.vertices, draw.shader, debug_view);
+        }
+        if export_requested {
+            let name = format!("{}_t{:.2}", shader_mode.name().to_lowercase(), time);
+            export_draws(&draws, &name);
+        }
+
+        if show_orbits && debug_view.is_shaded() {
+            // Lines don't write depth, so the trail blends over its path
+            let clip = framebuffer.clip_rect();
+            for (trail, orbit) in trails.iter().zip(orbits.iter()) {
+                draw_line_fragments(&mut framebuffer, &orbit_path_fragments(orbit, &view_matrix, &clip), 0.0);
+                draw_line_fragments(&mut framebuffer, &trail_fragments(trail, &view_matrix, &clip), 0.0);
+            }
+        }
+
+        framebuffer.resolve();
+        if debug_view.is_shaded() {
+            apply_bloom(&mut framebuffer, &bloom);
+            framebuffer.present(&tone_mapping);
+        } else {
+            debug_view.apply(&mut framebuffer);
+            framebuffer.present(&ToneMapSettings { operator: ToneMapping::Clamp, exposure: 1.0 });
+        }
+        if anti_aliasing == AntiAliasing::Fxaa {
+            apply_fxaa(&mut framebuffer);
+        }
+
+        hud.tick();
+        let mut status = vec![
+            format!("MODE: {}", shader_mode.name()),
+            format!("TIME: {:.2}", time),
+            format!("BODY: {}", bodies[selected_body].name()),
+            format!("SKY: {}", environments[environment_index].name()),
+            format!("BLOOM: {}", if bloom.enabled { "ON" } else { "OFF" }),
+            format!("TONEMAP: {}  EXPOSURE: {:.2}", tone_mapping.operator.name(), tone_mapping.exposure),
+            format!("AA: {}", anti_aliasing.name()),
+            format!("VIEW: {}", debug_view.name()),
+            format!("ORBITS: {}", if show_orbits { "ON" } else { "OFF" }),
+            format!("SCISSOR: {}", if scissor { "ON" } else { "OFF" }),
+            format!("SHADOWS: {}", shadow_mode.name()),
+            format!("TEXTURE: {}", planet_texture.as_ref().map_or("NONE", |texture| texture.filter.name())),
+        ];
+        // Read after drawing, so the level is the one this frame used
+        if let Some(sphere) = body_spheres.get(bodies[selected_body]) {
+            status.push(format!("MESH: {}", sphere.name()));
+        }
+        if shader_mode == ShaderMode::Model {
+            status.push(format!("MODEL: {}", model.as_ref().map_or("NONE", |model| model.name.as_str())));
         }
+        hud.draw(&mut framebuffer, &status);
 
         window
             .update_with_buffer(&framebuffer.buffer, framebuffer_width, framebuffer_height)
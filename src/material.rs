@@ -0,0 +1,125 @@
+use nalgebra_glm::Vec3;
+use std::path::Path;
+use crate::assets::AssetResolver;
+use crate::texture::{Texture, WrapMode};
+
+// Surface description read from an MTL file. Colors are linear reflectances.
+pub struct Material {
+    pub name: String,
+    // Ka, scaled by the scene's ambient light
+    pub ambient: Vec3,
+    // Kd, multiplied by the diffuse texture when there is one
+    pub diffuse: Vec3,
+    // Ks
+    pub specular: Vec3,
+    // Ns, the Blinn-Phong exponent
+    pub shininess: f32,
+    // map_Kd
+    pub diffuse_texture: Option<Texture>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: String::from("default"),
+            ambient: Vec3::new(1.0, 1.0, 1.0),
+            diffuse: Vec3::new(0.8, 0.8, 0.8),
+            specular: Vec3::zeros(),
+            shininess: 32.0,
+            diffuse_texture: None,
+        }
+    }
+}
+
+impl Material {
+    // Texture paths are resolved from `base_dir`, the OBJ file's directory.
+    // A texture that can't be found or loaded is replaced by a checkerboard,
+    // and the reason added to `warnings`.
+    pub fn from_mtl(
+        material: tobj::Material,
+        base_dir: &Path,
+        assets: &AssetResolver,
+        warnings: &mut Vec<String>,
+    ) -> Self {
+        let diffuse_texture = material.diffuse_texture.as_ref().map(|map| {
+            let (file, wrap) = parse_texture_map(map);
+            let mut texture = load_texture(file, base_dir, assets).unwrap_or_else(|reason| {
+                warnings.push(format!("material {}: {}, using a checkerboard", material.name, reason));
+                Texture::checkerboard()
+            });
+            texture.wrap = wrap;
+            texture
+        });
+
+        // A texture is usually meant to show as is, so default Kd to white with one
+        let default_diffuse = if diffuse_texture.is_some() { 1.0 } else { 0.8 };
+        let color = |value: Option<[f32; 3]>, default: f32| {
+            value.map_or(Vec3::repeat(default), |[r, g, b]| Vec3::new(r, g, b))
+        };
+
+        Material {
+            name: material.name,
+            ambient: color(material.ambient, 1.0),
+            diffuse: color(material.diffuse, default_diffuse),
+            specular: color(material.specular, 0.0),
+            shininess: material.shininess.unwrap_or(32.0),
+            diffuse_texture,
+        }
+    }
+}
+
+// File name and wrap mode of a texture map statement such as
+// `map_Kd -clamp on -s 2 2 madera.png`. Options other than -clamp are skipped;
+// what follows them is the file name, which may contain spaces.
+fn parse_texture_map(map: &str) -> (&str, WrapMode) {
+    let mut wrap = WrapMode::Repeat;
+    let mut rest = map.trim();
+    while let Some(option) = rest.strip_prefix('-') {
+        let (name, mut arguments) = split_word(option);
+        // Options taking up to three numbers take as many as are there
+        let (min, max) = match name {
+            "o" | "s" | "t" => (1, 3),
+            "mm" => (2, 2),
+            _ => (1, 1),
+        };
+        for taken in 0..max {
+            let (word, after) = split_word(arguments);
+            if taken >= min && word.parse::<f32>().is_err() {
+                break;
+            }
+            if name == "clamp" {
+                wrap = if word == "on" { WrapMode::Clamp } else { WrapMode::Repeat };
+            }
+            arguments = after;
+        }
+        rest = arguments;
+    }
+    (rest, wrap)
+}
+
+// First whitespace separated word of `text` and what follows it
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    (&text[..end], text[end..].trim_start())
+}
+
+fn load_texture(file: &str, base_dir: &Path, assets: &AssetResolver) -> Result<Texture, String> {
+    let path = assets.resolve(file, base_dir)
+        .ok_or_else(|| format!("texture {} not found", file))?;
+    Texture::load(&path)
+        .map_err(|err| format!("could not load texture {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_map_options_are_read_or_skipped() {
+        assert!(parse_texture_map("madera.png") == ("madera.png", WrapMode::Repeat));
+        assert!(parse_texture_map("-clamp on textura nueva.jpg") == ("textura nueva.jpg", WrapMode::Clamp));
+        assert!(parse_texture_map("-s 2 2 -clamp off -bm 0.5 -mm 0 1 a.tga") == ("a.tga", WrapMode::Repeat));
+        assert!(parse_texture_map("-o 0.5 -clamp on 3d.png") == ("3d.png", WrapMode::Clamp));
+    }
+}
@@ -0,0 +1,64 @@
+use nalgebra_glm::Mat4;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::assets::AssetResolver;
+use crate::material::Material;
+use crate::obj::{LoadOptions, LoadReport, Obj, ObjError};
+use crate::vertex::Vertex;
+
+// An OBJ file ready to draw: per-mesh vertex arrays and a transform that
+// centers the model on the origin and fits it inside the unit cube [-1, 1]
+pub struct Model {
+    pub name: String,
+    obj: Obj,
+    meshes: Vec<Vec<Vertex>>,
+    pub fit_matrix: Mat4,
+}
+
+impl Model {
+    pub fn load(path: &Path, assets: &AssetResolver, options: &LoadOptions) -> Result<Self, ObjError> {
+        let obj = Obj::load(path, assets, options)?;
+        let meshes = obj.get_mesh_vertex_arrays()
+            .into_iter()
+            .map(|(vertices, _)| vertices)
+            .collect();
+
+        let (min, max) = obj.bounding_box();
+        let center = (min + max) * 0.5;
+        let half_extent = ((max - min) * 0.5).max();
+        let scale = if half_extent > 0.0 { 1.0 / half_extent } else { 1.0 };
+        let fit_matrix = Mat4::new_scaling(scale) * Mat4::new_translation(&-center);
+
+        let name = path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_uppercase())
+            .unwrap_or_default();
+
+        Ok(Model { name, obj, meshes, fit_matrix })
+    }
+
+    // Defaults filled in while loading
+    pub fn report(&self) -> &LoadReport {
+        self.obj.report()
+    }
+
+    // Vertex array and material of every mesh
+    pub fn meshes(&self) -> impl Iterator<Item = (&[Vertex], &Material)> {
+        self.meshes.iter()
+            .enumerate()
+            .map(|(i, vertices)| (vertices.as_slice(), self.obj.mesh_material(i)))
+    }
+}
+
+// OBJ files in `directory`, sorted by name
+pub fn find_models<P: AsRef<Path>>(directory: P) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|e| e.eq_ignore_ascii_case("obj")))
+        .collect();
+    paths.sort();
+    paths
+}
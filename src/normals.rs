@@ -0,0 +1,175 @@
+use nalgebra_glm::{Vec2, Vec3, Vec4};
+use std::collections::HashMap;
+
+// Smooth normals for an indexed triangle list, one per corner: entry `i` is the
+// normal of the vertex at `indices[i]` in its triangle.
+//
+// Every triangle touching a position adds its face normal weighted by its area
+// and by its angle at that corner, so long thin triangles and finely split
+// faces don't pull the normal their way. Triangles are matched by position
+// rather than by index, because loaders split vertices along UV seams. A
+// triangle only contributes across an edge if its face normal is within
+// `crease_angle` (radians) of the corner's own, which keeps hard edges hard.
+pub fn smooth_normals(positions: &[Vec3], indices: &[u32], crease_angle: f32) -> Vec<Vec3> {
+    let corner = |i: usize| positions[indices[i] as usize];
+    let triangle_count = indices.len() / 3;
+
+    // Unit face normal and, for each of its corners, area * angle
+    let mut faces = Vec::with_capacity(triangle_count);
+    for triangle in 0..triangle_count {
+        let [a, b, c] = [0, 1, 2].map(|k| corner(triangle * 3 + k));
+        let cross = (b - a).cross(&(c - a));
+        let area = cross.norm() * 0.5;
+        let normal = if area > 0.0 { cross / (area * 2.0) } else { Vec3::zeros() };
+        let weights = [(a, b, c), (b, c, a), (c, a, b)].map(|(p, q, r)| area * angle_between(q - p, r - p));
+        faces.push((normal, weights));
+    }
+
+    // Corners sharing each position
+    let mut shared: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for i in 0..triangle_count * 3 {
+        shared.entry(position_key(&corner(i))).or_default().push(i);
+    }
+
+    let min_cos = crease_angle.cos();
+    (0..indices.len()).map(|i| {
+        if i >= triangle_count * 3 {
+            return Vec3::new(0.0, 1.0, 0.0);
+        }
+        let own = faces[i / 3].0;
+        let sum = shared[&position_key(&corner(i))].iter()
+            .map(|&j| (&faces[j / 3], j % 3))
+            .filter(|((normal, _), _)| normal.dot(&own) >= min_cos)
+            .fold(Vec3::zeros(), |sum, ((normal, weights), k)| sum + normal * weights[k]);
+
+        if sum.norm() > 0.0 {
+            sum.normalize()
+        } else if own.norm() > 0.0 {
+            own
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        }
+    }).collect()
+}
+
+// Per-vertex tangents for normal mapping, in the spirit of MikkTSpace: each
+// triangle's tangent and bitangent follow the direction of increasing u and v
+// across it, are summed into its vertices weighted by corner angle, then the
+// tangent is made perpendicular to the vertex normal. `w` holds the sign that
+// gives the bitangent as `cross(normal, tangent) * w`, which flips on mirrored
+// UVs. Vertices without a usable UV gradient get any perpendicular tangent.
+pub fn tangents(positions: &[Vec3], normals: &[Vec3], tex_coords: &[Vec2], indices: &[u32]) -> Vec<Vec4> {
+    let mut tangents = vec![Vec3::zeros(); positions.len()];
+    let mut bitangents = vec![Vec3::zeros(); positions.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|k| triangle[k] as usize);
+        let (e1, e2) = (positions[b] - positions[a], positions[c] - positions[a]);
+        let (d1, d2) = (tex_coords[b] - tex_coords[a], tex_coords[c] - tex_coords[a]);
+        let det = d1.x * d2.y - d2.x * d1.y;
+        if det.abs() < 1e-12 {
+            continue;
+        }
+        let tangent = (e1 * d2.y - e2 * d1.y) / det;
+        let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+
+        for (p, q, r) in [(a, b, c), (b, c, a), (c, a, b)] {
+            let weight = angle_between(positions[q] - positions[p], positions[r] - positions[p]);
+            tangents[p] += tangent * weight;
+            bitangents[p] += bitangent * weight;
+        }
+    }
+
+    (0..positions.len()).map(|i| {
+        let normal = normals[i];
+        let tangent = tangents[i] - normal * normal.dot(&tangents[i]);
+        let tangent = if tangent.norm() > 1e-12 { tangent.normalize() } else { perpendicular(&normal) };
+        let handedness = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+        Vec4::new(tangent.x, tangent.y, tangent.z, handedness)
+    }).collect()
+}
+
+fn angle_between(u: Vec3, v: Vec3) -> f32 {
+    let lengths = u.norm() * v.norm();
+    if lengths > 0.0 { (u.dot(&v) / lengths).clamp(-1.0, 1.0).acos() } else { 0.0 }
+}
+
+// Some unit vector perpendicular to `normal`
+fn perpendicular(normal: &Vec3) -> Vec3 {
+    let axis = if normal.x.abs() < 0.9 { Vec3::x() } else { Vec3::y() };
+    let tangent = axis - normal * normal.dot(&axis);
+    if tangent.norm() > 0.0 { tangent.normalize() } else { Vec3::x() }
+}
+
+// Exact position, usable as a hash key. `-0.0` is folded into `0.0`.
+fn position_key(p: &Vec3) -> [u32; 3] {
+    [p.x, p.y, p.z].map(|v| (v + 0.0).to_bits())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit cube made of 12 triangles, each with its own vertices as in a file
+    // without normals, wound so cross products point out
+    fn cube() -> (Vec<Vec3>, Vec<u32>) {
+        let faces = [
+            [[0, 0, 0], [0, 1, 0], [1, 1, 0], [1, 0, 0]],
+            [[0, 0, 1], [1, 0, 1], [1, 1, 1], [0, 1, 1]],
+            [[0, 0, 0], [1, 0, 0], [1, 0, 1], [0, 0, 1]],
+            [[0, 1, 0], [0, 1, 1], [1, 1, 1], [1, 1, 0]],
+            [[0, 0, 0], [0, 0, 1], [0, 1, 1], [0, 1, 0]],
+            [[1, 0, 0], [1, 1, 0], [1, 1, 1], [1, 0, 1]],
+        ];
+        let mut positions = Vec::new();
+        for face in faces {
+            let [a, b, c, d] = face.map(|[x, y, z]| Vec3::new(x as f32, y as f32, z as f32));
+            positions.extend([a, b, c, a, c, d]);
+        }
+        let indices = (0..positions.len() as u32).collect();
+        (positions, indices)
+    }
+
+    #[test]
+    fn creases_keep_cube_faces_flat() {
+        let (positions, indices) = cube();
+        let normals = smooth_normals(&positions, &indices, 60f32.to_radians());
+
+        for triangle in 0..indices.len() / 3 {
+            let [a, b, c] = [0, 1, 2].map(|k| positions[triangle * 3 + k]);
+            let face = (b - a).cross(&(c - a)).normalize();
+            for k in 0..3 {
+                assert!((normals[triangle * 3 + k] - face).norm() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn without_creases_corners_point_along_the_diagonal() {
+        let (positions, indices) = cube();
+        let normals = smooth_normals(&positions, &indices, std::f32::consts::PI);
+        let center = Vec3::repeat(0.5);
+
+        // The angle weighting evens out the two triangles each face has at
+        // some corners and one at others
+        for (position, normal) in positions.iter().zip(&normals) {
+            let diagonal = (position - center).normalize();
+            assert!((normal - diagonal).norm() < 1e-5, "{:?} at {:?}", normal, position);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_u_and_flip_with_mirrored_uvs() {
+        let positions = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        let normals = vec![Vec3::new(0.0, 0.0, 1.0); 3];
+        let indices = vec![0, 1, 2];
+
+        let uvs = vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)];
+        let tangent = tangents(&positions, &normals, &uvs, &indices)[0];
+        assert!((tangent - Vec4::new(1.0, 0.0, 0.0, 1.0)).norm() < 1e-5);
+
+        let mirrored: Vec<Vec2> = uvs.iter().map(|uv| Vec2::new(uv.x, -uv.y)).collect();
+        let tangent = tangents(&positions, &normals, &mirrored, &indices)[0];
+        assert!((tangent - Vec4::new(1.0, 0.0, 0.0, -1.0)).norm() < 1e-5);
+    }
+}
@@ -1,62 +1,502 @@
-use tobj;
-use nalgebra_glm::{Vec2, Vec3};
+use nalgebra_glm::{Vec2, Vec3, Vec4};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use crate::assets::AssetResolver;
+use crate::material::Material;
+use crate::normals::{smooth_normals, tangents};
 use crate::vertex::Vertex;
 
 pub struct Obj {
     meshes: Vec<Mesh>,
+    materials: Vec<Material>,
+    // Used for meshes without a material
+    default_material: Material,
+    report: LoadReport,
 }
 
 struct Mesh {
+    name: String,
     vertices: Vec<Vec3>,
     normals: Vec<Vec3>,
     texcoords: Vec<Vec2>,
+    // Empty unless generated
+    tangents: Vec<Vec4>,
     indices: Vec<u32>,
+    // Index into `Obj::materials`
+    material: Option<usize>,
+}
+
+// Axis pointing up in the file
+#[derive(Clone, Copy, PartialEq)]
+pub enum UpAxis {
+    // Most tools: x right, y up
+    Y,
+    // Blender, 3ds Max, Unreal: x right, z up, y away from the front view
+    Z,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Handedness {
+    // OBJ convention: with y up and x right, z points out of the screen
+    Right,
+    // DirectX style: with y up and x right, z points into the screen
+    Left,
+}
+
+// Where texture coordinate (0, 0) sits on the image
+#[derive(Clone, Copy, PartialEq)]
+pub enum UvOrigin {
+    // OBJ convention, v grows upwards
+    BottomLeft,
+    // v grows downwards like image rows
+    TopLeft,
+}
+
+impl UpAxis {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "y" => Some(UpAxis::Y),
+            "z" => Some(UpAxis::Z),
+            _ => None,
+        }
+    }
+}
+
+impl Handedness {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "right" => Some(Handedness::Right),
+            "left" => Some(Handedness::Left),
+            _ => None,
+        }
+    }
+}
+
+impl UvOrigin {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "bottom-left" => Some(UvOrigin::BottomLeft),
+            "top-left" => Some(UvOrigin::TopLeft),
+            _ => None,
+        }
+    }
+}
+
+// How `Obj::load` reads the file and what extra work it does on the meshes.
+// The defaults read a standard OBJ file and do no extra work.
+#[derive(Clone, Copy)]
+pub struct LoadOptions {
+    pub up_axis: UpAxis,
+    pub handedness: Handedness,
+    pub uv_origin: UvOrigin,
+    // Multiplies every position, e.g. 0.01 for a file in centimeters. The
+    // viewer fits every model to its bounding box, which undoes this, so it
+    // only matters to code that keeps model sizes.
+    pub unit_scale: f32,
+    // Compute smooth normals for meshes that have none
+    pub generate_normals: bool,
+    // Edges where faces meet at more than this angle (radians) stay sharp
+    // in generated normals
+    pub crease_angle: f32,
+    // Compute tangents for meshes with normals and texture coordinates
+    pub generate_tangents: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            up_axis: UpAxis::Y,
+            handedness: Handedness::Right,
+            uv_origin: UvOrigin::BottomLeft,
+            unit_scale: 1.0,
+            generate_normals: false,
+            crease_angle: 60f32.to_radians(),
+            generate_tangents: false,
+        }
+    }
+}
+
+impl LoadOptions {
+    // File position to model space, the renderer's screen-space axes: x right,
+    // y down and z into the screen, with the model's front facing the viewer
+    fn position(&self, v: &[f32]) -> Vec3 {
+        self.direction(v) * self.unit_scale
+    }
+
+    // Same axis change as `position`, without the scale, for normals
+    fn direction(&self, v: &[f32]) -> Vec3 {
+        let (x, mut y, mut z) = (v[0], v[1], v[2]);
+        // Mirror the axis pointing away from the front view to get a right-handed file
+        if self.handedness == Handedness::Left {
+            match self.up_axis {
+                UpAxis::Y => z = -z,
+                UpAxis::Z => y = -y,
+            }
+        }
+        // Then rotate z up to y up
+        let (y, z) = match self.up_axis {
+            UpAxis::Y => (y, z),
+            UpAxis::Z => (z, -y),
+        };
+        Vec3::new(x, -y, -z)
+    }
+
+    fn tex_coords(&self, t: &[f32]) -> Vec2 {
+        match self.uv_origin {
+            UvOrigin::BottomLeft => Vec2::new(t[0], 1.0 - t[1]),
+            UvOrigin::TopLeft => Vec2::new(t[0], t[1]),
+        }
+    }
+
+    // Mirroring flips triangle winding, which generated normals depend on
+    fn mirrors(&self) -> bool {
+        self.handedness == Handedness::Left
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjError {
+    // tobj could not read or parse the file
+    Load(tobj::LoadError),
+    // A face refers to a vertex the mesh doesn't have
+    IndexOutOfRange { mesh: String, index: u32, vertex_count: usize },
+    // A position with a NaN or infinite coordinate
+    InvalidPosition { mesh: String, vertex: usize },
+    // Normals or texture coordinates that don't pair up with the positions
+    AttributeCountMismatch { mesh: String, attribute: &'static str, count: usize, vertex_count: usize },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Load(err) => write!(f, "{}", err),
+            ObjError::IndexOutOfRange { mesh, index, vertex_count } => write!(
+                f, "mesh {}: index {} is out of range for {} vertices", mesh, index, vertex_count,
+            ),
+            ObjError::InvalidPosition { mesh, vertex } => write!(
+                f, "mesh {}: vertex {} has a NaN or infinite position", mesh, vertex,
+            ),
+            ObjError::AttributeCountMismatch { mesh, attribute, count, vertex_count } => write!(
+                f, "mesh {}: {} {} for {} vertices", mesh, count, attribute, vertex_count,
+            ),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Load(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<tobj::LoadError> for ObjError {
+    fn from(err: tobj::LoadError) -> Self {
+        ObjError::Load(err)
+    }
+}
+
+// What `Obj::load` filled in for data the file didn't have, by mesh name
+#[derive(Default)]
+pub struct LoadReport {
+    // Why the MTL file couldn't be read, if it couldn't
+    pub material_library: Option<String>,
+    // Textures replaced by a checkerboard, and why
+    pub textures: Vec<String>,
+    // Meshes without normals, given a constant one
+    pub missing_normals: Vec<String>,
+    // Meshes without normals, given smooth ones
+    pub generated_normals: Vec<String>,
+    // Meshes without texture coordinates, which all sample (0, 0)
+    pub missing_tex_coords: Vec<String>,
+    // Meshes drawn with the default material
+    pub missing_materials: Vec<String>,
+}
+
+impl LoadReport {
+    // One line per kind of defaulted data
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(reason) = &self.material_library {
+            warnings.push(format!("no materials: {}", reason));
+        }
+        warnings.extend(self.textures.iter().cloned());
+
+        let meshes = [
+            ("without normals", &self.missing_normals),
+            ("with generated normals", &self.generated_normals),
+            ("without texture coordinates", &self.missing_tex_coords),
+            ("with the default material", &self.missing_materials),
+        ];
+        for (what, names) in meshes {
+            if !names.is_empty() {
+                warnings.push(format!("meshes {}: {}", what, names.join(", ")));
+            }
+        }
+
+        warnings
+    }
 }
 
 impl Obj {
-    pub fn load(filename: &str) -> Result<Self, tobj::LoadError> {
-        let (models, _) = tobj::load_obj(filename, &tobj::LoadOptions {
+    // Textures named by the MTL file are looked up through `assets`. Anything
+    // filled in with a default is listed in `report()`.
+    pub fn load<P: AsRef<Path>>(path: P, assets: &AssetResolver, options: &LoadOptions) -> Result<Self, ObjError> {
+        let path = path.as_ref();
+        let (models, materials) = tobj::load_obj(path, &tobj::LoadOptions {
             single_index: true,
             triangulate: true,
-            ..Default::default()
+            // Only faces are drawn. Kept, points and lines turn into degenerate
+            // triangles with vertices missing the normals of the faces around them.
+            ignore_points: true,
+            ignore_lines: true,
         })?;
+        let mut report = LoadReport::default();
+
+        // A missing or broken MTL file leaves the model untextured rather than failing
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        let materials: Vec<Material> = materials
+            .unwrap_or_else(|err| {
+                report.material_library = Some(err.to_string());
+                Vec::new()
+            })
+            .into_iter()
+            .map(|material| Material::from_mtl(material, base_dir, assets, &mut report.textures))
+            .collect();
 
-        let meshes = models.into_iter().map(|model| {
-            let mesh = model.mesh;
-            Mesh {
-                vertices: mesh.positions.chunks(3)
-                    .map(|v| Vec3::new(v[0], -v[1], -v[2]))
-                    .collect(),
-                normals: mesh.normals.chunks(3)
-                    .map(|n| Vec3::new(n[0], -n[1], -n[2]))
-                    .collect(),
-                texcoords: mesh.texcoords.chunks(2)
-                    .map(|t| Vec2::new(t[0], 1.0 - t[1]))
-                    .collect(),
-                indices: mesh.indices,
+        let mut meshes = models.into_iter()
+            .map(|model| Mesh::from_tobj(model, materials.len(), options))
+            .collect::<Result<Vec<Mesh>, ObjError>>()?;
+
+        for mesh in &mut meshes {
+            if mesh.normals.is_empty() && options.generate_normals {
+                mesh.generate_normals(options.crease_angle);
+                report.generated_normals.push(mesh.name.clone());
+            }
+            if options.generate_tangents && !mesh.normals.is_empty() && !mesh.texcoords.is_empty() {
+                mesh.tangents = tangents(&mesh.vertices, &mesh.normals, &mesh.texcoords, &mesh.indices);
             }
-        }).collect();
 
-        Ok(Obj { meshes })
+            let defaulted = [
+                (mesh.normals.is_empty(), &mut report.missing_normals),
+                (mesh.texcoords.is_empty(), &mut report.missing_tex_coords),
+                (mesh.material.is_none(), &mut report.missing_materials),
+            ];
+            for (missing, names) in defaulted {
+                if missing {
+                    names.push(mesh.name.clone());
+                }
+            }
+        }
+
+        Ok(Obj { meshes, materials, default_material: Material::default(), report })
+    }
+
+    pub fn report(&self) -> &LoadReport {
+        &self.report
     }
 
-    pub fn get_vertex_array(&self) -> Vec<Vertex> {
-        let mut vertices = Vec::new();
+    // One vertex array per mesh, with the material to draw it with
+    pub fn get_mesh_vertex_arrays(&self) -> Vec<(Vec<Vertex>, &Material)> {
+        self.meshes.iter()
+            .enumerate()
+            .map(|(i, mesh)| (mesh.vertex_array(), self.mesh_material(i)))
+            .collect()
+    }
+
+    pub fn mesh_material(&self, mesh: usize) -> &Material {
+        self.meshes[mesh].material.map_or(&self.default_material, |id| &self.materials[id])
+    }
 
-        for mesh in &self.meshes {
-            for &index in &mesh.indices {
-                let position = mesh.vertices[index as usize];
-                let normal = mesh.normals.get(index as usize)
-                    .cloned()
-                    .unwrap_or(Vec3::new(0.0, 1.0, 0.0));
-                let tex_coords = mesh.texcoords.get(index as usize)
-                    .cloned()
-                    .unwrap_or(Vec2::new(0.0, 0.0));
+    // Corners of the box holding every vertex, or the origin for an empty model
+    pub fn bounding_box(&self) -> (Vec3, Vec3) {
+        let mut positions = self.meshes.iter().flat_map(|mesh| mesh.vertices.iter());
+        let Some(first) = positions.next() else {
+            return (Vec3::zeros(), Vec3::zeros());
+        };
 
-                vertices.push(Vertex::new(position, normal, tex_coords));
+        positions.fold((*first, *first), |(min, max), p| (min.inf(p), max.sup(p)))
+    }
+}
+
+impl Mesh {
+    // Checks the mesh so that every index can be followed safely. A material id
+    // past `material_count` counts as no material.
+    fn from_tobj(model: tobj::Model, material_count: usize, options: &LoadOptions) -> Result<Self, ObjError> {
+        let (name, mut mesh) = (model.name, model.mesh);
+        let vertex_count = mesh.positions.len() / 3;
+
+        let attributes = [("normals", &mesh.normals, 3), ("texture coordinates", &mesh.texcoords, 2)];
+        for (attribute, values, size) in attributes {
+            if !values.is_empty() && values.len() != vertex_count * size {
+                return Err(ObjError::AttributeCountMismatch {
+                    mesh: name,
+                    attribute,
+                    count: values.len() / size,
+                    vertex_count,
+                });
+            }
+        }
+        if let Some(vertex) = mesh.positions.iter().position(|p| !p.is_finite()) {
+            return Err(ObjError::InvalidPosition { mesh: name, vertex: vertex / 3 });
+        }
+        if let Some(&index) = mesh.indices.iter().find(|&&i| i as usize >= vertex_count) {
+            return Err(ObjError::IndexOutOfRange { mesh: name, index, vertex_count });
+        }
+
+        if options.mirrors() {
+            for triangle in mesh.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
             }
         }
 
+        Ok(Mesh {
+            vertices: mesh.positions.chunks(3)
+                .map(|v| options.position(v))
+                .collect(),
+            normals: mesh.normals.chunks(3)
+                .map(|n| options.direction(n))
+                .collect(),
+            texcoords: mesh.texcoords.chunks(2)
+                .map(|t| options.tex_coords(t))
+                .collect(),
+            tangents: Vec::new(),
+            indices: mesh.indices,
+            material: mesh.material_id.filter(|id| *id < material_count),
+            name,
+        })
+    }
+
+    // Replaces the normals with smooth ones. A vertex whose corners get
+    // different normals, along a crease, is split into one per normal.
+    fn generate_normals(&mut self, crease_angle: f32) {
+        let corner_normals = smooth_normals(&self.vertices, &self.indices, crease_angle);
+        let mut split: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+        let (mut vertices, mut normals, mut texcoords) = (Vec::new(), Vec::new(), Vec::new());
+
+        let indices = self.indices.iter().zip(&corner_normals).map(|(&index, normal)| {
+            let key = (index, [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()]);
+            *split.entry(key).or_insert_with(|| {
+                vertices.push(self.vertices[index as usize]);
+                normals.push(*normal);
+                if let Some(uv) = self.texcoords.get(index as usize) {
+                    texcoords.push(*uv);
+                }
+                (vertices.len() - 1) as u32
+            })
+        }).collect();
+
+        self.vertices = vertices;
+        self.normals = normals;
+        self.texcoords = texcoords;
+        self.indices = indices;
+    }
+
+    fn vertex_array(&self) -> Vec<Vertex> {
+        let mut vertices = Vec::with_capacity(self.indices.len());
+
+        for &index in &self.indices {
+            let position = self.vertices[index as usize];
+            let normal = self.normals.get(index as usize)
+                .cloned()
+                .unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+            let tex_coords = self.texcoords.get(index as usize)
+                .cloned()
+                .unwrap_or(Vec2::new(0.0, 0.0));
+            let tangent = self.tangents.get(index as usize)
+                .cloned()
+                .unwrap_or(Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+            vertices.push(Vertex { tangent, ..Vertex::new(position, normal, tex_coords) });
+        }
+
         vertices
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Single triangle mesh
+    fn triangle() -> tobj::Mesh {
+        tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        }
+    }
+
+    fn validate(mesh: tobj::Mesh) -> Result<Mesh, ObjError> {
+        Mesh::from_tobj(tobj::Model::new(mesh, String::from("test")), 0, &LoadOptions::default())
+    }
+
+    #[test]
+    fn valid_mesh_loads() {
+        let mesh = validate(triangle()).unwrap();
+        assert_eq!(mesh.vertex_array().len(), 3);
+    }
+
+    #[test]
+    fn malformed_meshes_are_rejected() {
+        let out_of_range = tobj::Mesh { indices: vec![0, 1, 3], ..triangle() };
+        assert!(matches!(validate(out_of_range), Err(ObjError::IndexOutOfRange { index: 3, .. })));
+
+        let mut nan = triangle();
+        nan.positions[4] = f32::NAN;
+        assert!(matches!(validate(nan), Err(ObjError::InvalidPosition { vertex: 1, .. })));
+
+        let missing_normal = tobj::Mesh { normals: vec![0.0, 0.0, 1.0], ..triangle() };
+        assert!(matches!(
+            validate(missing_normal),
+            Err(ObjError::AttributeCountMismatch { attribute: "normals", count: 1, vertex_count: 3, .. }),
+        ));
+    }
+
+    #[test]
+    fn axis_conventions_agree_on_up_and_front() {
+        let y_up = LoadOptions::default();
+        let z_up = LoadOptions { up_axis: UpAxis::Z, ..y_up };
+        let left = LoadOptions { handedness: Handedness::Left, ..y_up };
+
+        // Up is -y on screen
+        let up = Vec3::new(0.0, -1.0, 0.0);
+        assert_eq!(y_up.direction(&[0.0, 1.0, 0.0]), up);
+        assert_eq!(z_up.direction(&[0.0, 0.0, 1.0]), up);
+        // The side seen in the file's front view faces the viewer, -z on screen
+        let front = Vec3::new(0.0, 0.0, -1.0);
+        assert_eq!(y_up.direction(&[0.0, 0.0, 1.0]), front);
+        assert_eq!(z_up.direction(&[0.0, -1.0, 0.0]), front);
+        assert_eq!(left.direction(&[0.0, 0.0, -1.0]), front);
+    }
+
+    #[test]
+    fn unit_scale_reaches_mesh_vertices() {
+        let options = LoadOptions { unit_scale: 0.01, ..LoadOptions::default() };
+        let mesh = Mesh::from_tobj(tobj::Model::new(triangle(), String::new()), 0, &options).unwrap();
+        assert_eq!(mesh.vertices[1], Vec3::new(0.01, 0.0, 0.0));
+        assert_eq!(mesh.vertices[2], Vec3::new(0.0, -0.01, 0.0));
+    }
+
+    #[test]
+    fn mirroring_keeps_generated_normals_on_the_winding_side() {
+        let generated_normal = |options: &LoadOptions| {
+            let mesh = tobj::Mesh { normals: Vec::new(), ..triangle() };
+            let mut mesh = Mesh::from_tobj(tobj::Model::new(mesh, String::new()), 0, options).unwrap();
+            mesh.generate_normals(options.crease_angle);
+            mesh.normals[0]
+        };
+
+        // Counterclockwise seen from +z, which is the viewer's side in a
+        // right-handed file and the far side in a left-handed one
+        let right = LoadOptions::default();
+        let left = LoadOptions { handedness: Handedness::Left, ..right };
+        assert_eq!(generated_normal(&right), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(generated_normal(&left), Vec3::new(0.0, 0.0, 1.0));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+
+use nalgebra_glm::{Mat4, Vec3, Vec4};
+use crate::fragment::Fragment;
+use crate::framebuffer::Rect;
+use crate::line::{line_with_style, LineStyle};
+use crate::vertex::Vertex;
+
+const PATH_SEGMENTS: usize = 128;
+const PATH_COLOR: Vec3 = Vec3::new(0.12, 0.16, 0.28);
+const PATH_STYLE: LineStyle = LineStyle { thickness: 1.0, antialiased: true };
+const TRAIL_COLOR: Vec3 = Vec3::new(0.55, 0.7, 1.0);
+const TRAIL_STYLE: LineStyle = LineStyle { thickness: 2.0, antialiased: true };
+
+// Elliptical orbit around `center`, in world (screen) units. The ellipse lies
+// in the xy plane and is then tilted about the x axis by `inclination`.
+pub struct Orbit {
+    pub center: Vec3,
+    pub semi_major: f32,
+    pub semi_minor: f32,
+    pub inclination: f32,
+    // Radians per unit of time
+    pub speed: f32,
+}
+
+impl Orbit {
+    pub fn circular(center: Vec3, radius: f32, speed: f32) -> Self {
+        Orbit {
+            center,
+            semi_major: radius,
+            semi_minor: radius,
+            inclination: 0.0,
+            speed,
+        }
+    }
+
+    fn point_at(&self, angle: f32) -> Vec3 {
+        let x = self.semi_major * angle.cos();
+        let y = self.semi_minor * angle.sin();
+        let (sin_i, cos_i) = self.inclination.sin_cos();
+        self.center + Vec3::new(x, y * cos_i, y * sin_i)
+    }
+
+    pub fn position(&self, time: f32) -> Vec3 {
+        self.point_at(time * self.speed)
+    }
+
+    // Closed loop of points around the whole ellipse
+    pub fn path(&self, segments: usize) -> Vec<Vec3> {
+        (0..=segments)
+            .map(|i| self.point_at(i as f32 / segments as f32 * std::f32::consts::TAU))
+            .collect()
+    }
+}
+
+// Recent positions of a body, oldest first
+pub struct Trail {
+    positions: VecDeque<Vec3>,
+    capacity: usize,
+}
+
+impl Trail {
+    pub fn new(capacity: usize) -> Self {
+        Trail {
+            positions: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, position: Vec3) {
+        if self.positions.len() == self.capacity {
+            self.positions.pop_front();
+        }
+        self.positions.push_back(position);
+    }
+}
+
+fn project(point: &Vec3, view_matrix: &Mat4) -> Vertex {
+    let transformed = view_matrix * Vec4::new(point.x, point.y, point.z, 1.0);
+    Vertex {
+        transformed_position: Vec3::new(transformed.x, transformed.y, transformed.z) / transformed.w,
+        ..Vertex::default()
+    }
+}
+
+fn polyline(points: &[Vec3], view_matrix: &Mat4, style: &LineStyle, clip: &Rect) -> Vec<Vec<Fragment>> {
+    let vertices: Vec<Vertex> = points.iter().map(|p| project(p, view_matrix)).collect();
+    vertices.windows(2)
+        .map(|pair| line_with_style(&pair[0], &pair[1], style, clip))
+        .collect()
+}
+
+// Line fragments for the full orbit ellipse as seen by the camera
+pub fn orbit_path_fragments(orbit: &Orbit, view_matrix: &Mat4, clip: &Rect) -> Vec<Fragment> {
+    let mut fragments: Vec<Fragment> = polyline(&orbit.path(PATH_SEGMENTS), view_matrix, &PATH_STYLE, clip)
+        .into_iter()
+        .flatten()
+        .collect();
+    for fragment in fragments.iter_mut() {
+        fragment.color = PATH_COLOR;
+    }
+    fragments
+}
+
+// Line fragments for the trail, fading out towards its oldest end
+pub fn trail_fragments(trail: &Trail, view_matrix: &Mat4, clip: &Rect) -> Vec<Fragment> {
+    let points: Vec<Vec3> = trail.positions.iter().copied().collect();
+    let segments = polyline(&points, view_matrix, &TRAIL_STYLE, clip);
+    let count = segments.len();
+
+    segments.into_iter()
+        .enumerate()
+        .flat_map(|(i, mut segment)| {
+            let fade = (i + 1) as f32 / count as f32;
+            for fragment in segment.iter_mut() {
+                fragment.color = TRAIL_COLOR;
+                fragment.alpha *= fade * fade;
+            }
+            segment
+        })
+        .collect()
+}
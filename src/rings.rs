@@ -0,0 +1,232 @@
+use nalgebra_glm::{Vec2, Vec3};
+use std::error::Error;
+use std::fmt;
+use crate::fragment_shaders::{fbm, smoothstep};
+use crate::vertex::Vertex;
+
+// Entries in the radial profile, enough for gaps a few pixels wide on screen
+const PROFILE_SAMPLES: usize = 1024;
+
+// Radial structure of the default rings, loosely after Saturn's, as fractions
+// of the way from the inner to the outer edge: (start, end, optical depth)
+const BANDS: [(f32, f32, f32); 3] = [
+    (0.00, 0.30, 0.12), // C ring, faint and dusty
+    (0.30, 0.62, 1.80), // B ring, the densest
+    (0.62, 1.00, 0.70), // A ring
+];
+// Empty divisions cut out of the bands: (start, end)
+const GAPS: [(f32, f32); 3] = [
+    (0.09, 0.10),   // Maxwell gap
+    (0.615, 0.685), // Cassini division
+    (0.895, 0.915), // Encke gap
+];
+// Ringlets per unit of the profile, and how much they vary a band's depth
+const RINGLET_FREQUENCY: f32 = 90.0;
+const RINGLET_CONTRAST: f32 = 0.7;
+
+// Angular size of the star seen from the rings, softening the planet's shadow
+const PENUMBRA: f32 = 0.02;
+
+// A ring system around a planet, in the planet's object space: the planet is
+// a unit sphere at the origin and the rings lie in its equatorial plane, y = 0.
+// How much light the rings stop comes from a radial opacity profile shared by
+// their mesh, their shading and the shadow they cast.
+pub struct RingSystem {
+    inner_radius: f32,
+    outer_radius: f32,
+    // Fraction of light stopped by the rings, from the inner to the outer edge
+    opacity: Vec<f32>,
+    // `cumulative[i]` is the sum of the first `i` opacities, for averaging
+    // the profile over a range of radii
+    cumulative: Vec<f32>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RingError {
+    // No opacities to spread between the radii
+    EmptyProfile,
+    // The inner radius is not smaller than the outer one, or isn't a number
+    InvalidRadii { inner_radius: f32, outer_radius: f32 },
+}
+
+impl fmt::Display for RingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RingError::EmptyProfile => write!(f, "ring profile has no samples"),
+            RingError::InvalidRadii { inner_radius, outer_radius } => write!(
+                f, "ring inner radius {} is not inside the outer radius {}", inner_radius, outer_radius,
+            ),
+        }
+    }
+}
+
+impl Error for RingError {}
+
+impl RingSystem {
+    // Rings between the radii with the default bands and gaps
+    pub fn new(inner_radius: f32, outer_radius: f32) -> Result<Self, RingError> {
+        let profile = (0..PROFILE_SAMPLES).map(|i| {
+            let t = (i as f32 + 0.5) / PROFILE_SAMPLES as f32;
+            if GAPS.iter().any(|&(start, end)| t >= start && t < end) {
+                return 0.0;
+            }
+            let depth = BANDS.iter()
+                .find(|&&(start, end, _)| t >= start && t < end)
+                .map_or(0.0, |&(_, _, depth)| depth);
+            let ringlets = 1.0 + (fbm(Vec3::new(t * RINGLET_FREQUENCY, 0.0, 0.0), 3) * 2.0 - 1.0) * RINGLET_CONTRAST;
+            1.0 - (-depth * ringlets).exp()
+        }).collect();
+        RingSystem::from_profile(inner_radius, outer_radius, profile)
+    }
+
+    // Rings with the given opacities, evenly spaced from the inner to the
+    // outer radius. Zero entries are gaps left out of the mesh.
+    pub fn from_profile(inner_radius: f32, outer_radius: f32, opacity: Vec<f32>) -> Result<Self, RingError> {
+        if opacity.is_empty() {
+            return Err(RingError::EmptyProfile);
+        }
+        if inner_radius.is_nan() || outer_radius.is_nan() || inner_radius >= outer_radius {
+            return Err(RingError::InvalidRadii { inner_radius, outer_radius });
+        }
+        let mut cumulative = Vec::with_capacity(opacity.len() + 1);
+        let mut sum = 0.0;
+        cumulative.push(sum);
+        for value in &opacity {
+            sum += value;
+            cumulative.push(sum);
+        }
+        Ok(RingSystem { inner_radius, outer_radius, opacity, cumulative })
+    }
+
+    // Opacity at `radius` averaged over `footprint` around it, so gaps and
+    // ringlets thinner than a pixel blend in instead of flickering
+    pub fn opacity(&self, radius: f32, footprint: f32) -> f32 {
+        let scale = self.opacity.len() as f32 / (self.outer_radius - self.inner_radius);
+        let center = (radius - self.inner_radius) * scale;
+        let half_width = (footprint * scale).max(1.0) * 0.5;
+        let sum = self.cumulative_at(center + half_width) - self.cumulative_at(center - half_width);
+        sum / (half_width * 2.0)
+    }
+
+    // Light let through to `point` on the planet from `light_dir` (towards the
+    // light) by the rings in between
+    pub fn shadow_on_planet(&self, point: Vec3, light_dir: Vec3, footprint: f32) -> f32 {
+        // Points on the far side of the ring plane from the light only
+        if point.y * light_dir.y >= 0.0 {
+            return 1.0;
+        }
+        let hit = point - light_dir * (point.y / light_dir.y);
+        1.0 - self.opacity(Vec2::new(hit.x, hit.z).magnitude(), footprint)
+    }
+
+    // Light reaching `point` on the rings from `light_dir` past the planet
+    pub fn planet_shadow(&self, point: Vec3, light_dir: Vec3) -> f32 {
+        // Closest the ray towards the light passes to the planet's center
+        let along = -point.dot(&light_dir);
+        if along <= 0.0 {
+            return 1.0;
+        }
+        let distance = (point + light_dir * along).magnitude();
+        smoothstep(1.0 - PENUMBRA, 1.0 + PENUMBRA, distance)
+    }
+
+    // Running opacity sum up to a fractional profile position, with empty
+    // space past both edges
+    fn cumulative_at(&self, position: f32) -> f32 {
+        let position = position.clamp(0.0, self.opacity.len() as f32);
+        let index = (position as usize).min(self.opacity.len() - 1);
+        self.cumulative[index] + self.opacity[index] * (position - index as f32)
+    }
+}
+
+// Flat mesh of the rings' solid bands, each an annulus of `segments` quads
+// facing +y. `tex_coords` are the angle around and the fraction of the way out
+// from the inner to the outer edge.
+pub fn generate_ring(rings: &RingSystem, segments: u32) -> Vec<Vertex> {
+    let samples = rings.opacity.len();
+    let width = rings.outer_radius - rings.inner_radius;
+    let mut vertices = Vec::new();
+
+    let mut start = 0;
+    while start < samples {
+        if rings.opacity[start] == 0.0 {
+            start += 1;
+            continue;
+        }
+        let end = (start..samples).find(|&i| rings.opacity[i] == 0.0).unwrap_or(samples);
+        let (inner, outer) = (start as f32 / samples as f32, end as f32 / samples as f32);
+        push_annulus(&mut vertices, rings.inner_radius + inner * width, rings.inner_radius + outer * width, inner, outer, segments);
+        start = end;
+    }
+
+    vertices
+}
+
+fn push_annulus(vertices: &mut Vec<Vertex>, inner_radius: f32, outer_radius: f32, inner_v: f32, outer_v: f32, segments: u32) {
+    let normal = Vec3::new(0.0, 1.0, 0.0);
+    let corner = |i: u32, radius: f32, v: f32| {
+        let u = i as f32 / segments as f32;
+        let (sin_a, cos_a) = (u * 2.0 * std::f32::consts::PI).sin_cos();
+        Vertex::new(Vec3::new(radius * cos_a, 0.0, radius * sin_a), normal, Vec2::new(u, v))
+    };
+
+    for i in 0..segments {
+        let outer = corner(i, outer_radius, outer_v);
+        let inner = corner(i, inner_radius, inner_v);
+        let next_outer = corner(i + 1, outer_radius, outer_v);
+        let next_inner = corner(i + 1, inner_radius, inner_v);
+
+        vertices.extend([outer, next_outer.clone(), inner.clone()]);
+        vertices.extend([inner, next_outer, next_inner]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_or_zero_width_profiles_are_rejected() {
+        assert_eq!(RingSystem::from_profile(1.2, 2.0, Vec::new()).err(), Some(RingError::EmptyProfile));
+        assert_eq!(
+            RingSystem::new(2.0, 2.0).err(),
+            Some(RingError::InvalidRadii { inner_radius: 2.0, outer_radius: 2.0 }),
+        );
+        assert!(RingSystem::new(f32::NAN, 2.0).is_err());
+    }
+
+    #[test]
+    fn gaps_are_left_out_of_the_mesh_and_let_light_through() {
+        let rings = RingSystem::new(1.2, 2.0).unwrap();
+        let (start, end) = GAPS[1];
+        let cassini = 1.2 + (start + end) * 0.5 * 0.8;
+
+        // Annuli for the bands between the Maxwell, Cassini and Encke gaps
+        let mesh = generate_ring(&rings, 8);
+        assert_eq!(mesh.len(), (GAPS.len() + 1) * 8 * 6);
+        for vertex in &mesh {
+            let radius = Vec2::new(vertex.position.x, vertex.position.z).magnitude();
+            // Band edges land on whole profile samples
+            assert!((radius - cassini).abs() >= (end - start - 1.0 / PROFILE_SAMPLES as f32) * 0.8 * 0.5);
+        }
+
+        // A light straight above the ring plane shines through the gap onto
+        // the planet, and is mostly stopped by the B ring
+        let light_dir = Vec3::new(0.0, 1.0, 0.0);
+        let below = |radius: f32| Vec3::new(radius, -0.1, 0.0);
+        assert_eq!(rings.shadow_on_planet(below(cassini), light_dir, 0.0), 1.0);
+        assert!(rings.shadow_on_planet(below(1.6), light_dir, 0.0) < 0.5);
+        // Points above the rings are not shadowed by them
+        assert_eq!(rings.shadow_on_planet(Vec3::new(1.6, 0.1, 0.0), light_dir, 0.0), 1.0);
+    }
+
+    #[test]
+    fn planet_shades_the_rings_behind_it() {
+        let rings = RingSystem::new(1.2, 2.0).unwrap();
+        let light_dir = Vec3::new(-1.0, 0.0, 0.0);
+
+        assert_eq!(rings.planet_shadow(Vec3::new(1.5, 0.0, 0.0), light_dir), 0.0);
+        assert_eq!(rings.planet_shadow(Vec3::new(-1.5, 0.0, 0.0), light_dir), 1.0);
+        assert_eq!(rings.planet_shadow(Vec3::new(1.5, 0.0, 1.5), light_dir), 1.0);
+    }
+}
@@ -10,7 +10,8 @@ pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
     vertex.position.z,
     1.0
   );
-  let transformed = uniforms.model_matrix * position;
+  let model_view = uniforms.view_matrix * uniforms.model_matrix;
+  let transformed = model_view * position;
 
   // Perform perspective division
   let w = transformed.w;
@@ -22,12 +23,15 @@ pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
 
   // Transform normal
 
+  // Mat3::new takes rows but the indices walk down columns, so this is the
+  // transpose of the model-view matrix and its inverse is the inverse
+  // transpose normals need
   let model_mat3 = Mat3::new(
-    uniforms.model_matrix[0], uniforms.model_matrix[1], uniforms.model_matrix[2],
-    uniforms.model_matrix[4], uniforms.model_matrix[5], uniforms.model_matrix[6],
-    uniforms.model_matrix[8], uniforms.model_matrix[9], uniforms.model_matrix[10]
+    model_view[0], model_view[1], model_view[2],
+    model_view[4], model_view[5], model_view[6],
+    model_view[8], model_view[9], model_view[10]
   );
-  let normal_matrix = model_mat3.transpose().try_inverse().unwrap_or(Mat3::identity());
+  let normal_matrix = model_mat3.try_inverse().unwrap_or(Mat3::identity());
 
   let transformed_normal = normal_matrix * vertex.normal;
 
@@ -36,9 +40,26 @@ pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
     position: vertex.position,
     normal: vertex.normal,
     tex_coords: vertex.tex_coords,
+    tangent: vertex.tangent,
     color: vertex.color,
     transformed_position,
     transformed_normal,
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nalgebra_glm::Vec2;
+
+  #[test]
+  fn normals_turn_with_the_model() {
+    // A quarter turn around z takes the +x normal to +y, whatever the scale
+    let model_matrix = crate::create_model_matrix(Vec3::zeros(), 2.0, Vec3::new(0.0, 0.0, std::f32::consts::FRAC_PI_2));
+    let uniforms = Uniforms::new(model_matrix, nalgebra_glm::Mat4::identity());
+    let vertex = Vertex::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec2::zeros());
+
+    let normal = vertex_shader(&vertex, &uniforms).transformed_normal.normalize();
+    assert!((normal - Vec3::new(0.0, 1.0, 0.0)).norm() < 1e-5);
+  }
+}
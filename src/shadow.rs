@@ -0,0 +1,330 @@
+use nalgebra_glm::{Mat4, Vec2, Vec3, Vec4};
+use crate::vertex::Vertex;
+
+// Texels along each side of a cube face, for a point light
+const CUBE_RESOLUTION: usize = 512;
+// Texels along each side of the single map of a directional light
+const DIRECTIONAL_RESOLUTION: usize = 1024;
+// Casters closer than this to a point light are left out of its cube
+const NEAR_PLANE: f32 = 1.0;
+// Space left around the casters when fitting a directional map to them
+const FIT_MARGIN: f32 = 4.0;
+// Receivers are moved towards the light before the depth test, by this many
+// texels plus SLOPE_BIAS times the tangent of the angle the light comes in at,
+// so surfaces don't shadow themselves
+const DEPTH_BIAS: f32 = 1.5;
+const SLOPE_BIAS: f32 = 2.0;
+const MAX_SLOPE: f32 = 8.0;
+// Texels from the center of the PCF kernel to its edge
+const PCF_RADIUS: i32 = 1;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ShadowLight {
+    // Light spreading out from a point, like the star
+    Point(Vec3),
+    // Parallel light coming from `direction` (towards the light)
+    Directional(Vec3),
+}
+
+// How bodies shadow each other: with a shadow map of everything casting
+// shadows, or analytically from the spheres in `Eclipses`
+#[derive(Clone, Copy, PartialEq)]
+pub enum ShadowMode {
+    Map,
+    Analytic,
+    Off,
+}
+
+impl ShadowMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShadowMode::Map => "SHADOW MAP",
+            ShadowMode::Analytic => "ANALYTIC",
+            ShadowMode::Off => "OFF",
+        }
+    }
+
+    pub fn next(&self) -> ShadowMode {
+        match self {
+            ShadowMode::Map => ShadowMode::Analytic,
+            ShadowMode::Analytic => ShadowMode::Off,
+            ShadowMode::Off => ShadowMode::Map,
+        }
+    }
+}
+
+// Depth of the casters closest to a light, stored per texel of a cube map
+// around a point light or of one map across a directional light, in world space
+#[derive(Default)]
+pub struct ShadowMap {
+    faces: Vec<ShadowFace>,
+}
+
+// One depth target, looking away from the light along `forward`
+struct ShadowFace {
+    origin: Vec3,
+    right: Vec3,
+    up: Vec3,
+    forward: Vec3,
+    // Perspective faces cover a 90 degree cone around `forward`, orthographic
+    // ones a square reaching `extent` from `origin` each way
+    perspective: bool,
+    extent: f32,
+    resolution: usize,
+    // Distance along `forward` to the closest caster, per texel
+    depth: Vec<f32>,
+}
+
+impl ShadowMap {
+    // Clears the map for `light` and draws the triangles of every caster, each
+    // a triangle list placed with its model matrix
+    pub fn render(&mut self, light: ShadowLight, casters: &[(&[Vertex], Mat4)]) {
+        let world: Vec<Vec<Vec3>> = casters.iter()
+            .map(|(vertices, model_matrix)| vertices.iter().map(|vertex| transform(model_matrix, vertex.position)).collect())
+            .collect();
+
+        match light {
+            ShadowLight::Point(position) => {
+                let axes = [Vec3::x(), -Vec3::x(), Vec3::y(), -Vec3::y(), Vec3::z(), -Vec3::z()];
+                self.faces.resize_with(axes.len(), || ShadowFace::new(CUBE_RESOLUTION));
+                for (face, forward) in self.faces.iter_mut().zip(axes) {
+                    face.look(position, forward, true, 1.0, CUBE_RESOLUTION);
+                }
+            }
+            ShadowLight::Directional(direction) => {
+                let forward = -direction.normalize();
+                self.faces.truncate(1);
+                if self.faces.is_empty() {
+                    self.faces.push(ShadowFace::new(DIRECTIONAL_RESOLUTION));
+                }
+                let face = &mut self.faces[0];
+                face.look(Vec3::zeros(), forward, false, 1.0, DIRECTIONAL_RESOLUTION);
+
+                // Square around the casters as seen from the light
+                let (mut min, mut max) = (Vec2::repeat(f32::INFINITY), Vec2::repeat(f32::NEG_INFINITY));
+                for p in world.iter().flatten() {
+                    let p = Vec2::new(p.dot(&face.right), p.dot(&face.up));
+                    min = min.inf(&p);
+                    max = max.sup(&p);
+                }
+                if min.x <= max.x {
+                    let center = (min + max) * 0.5;
+                    face.origin = face.right * center.x + face.up * center.y;
+                    face.extent = (max - min).max() * 0.5 + FIT_MARGIN;
+                }
+            }
+        }
+
+        for face in &mut self.faces {
+            face.depth.fill(f32::INFINITY);
+            for triangle in world.iter().flat_map(|positions| positions.chunks_exact(3)) {
+                face.draw_triangle(triangle);
+            }
+        }
+    }
+
+    // Fraction of the light reaching `point`, filtered across neighbouring
+    // texels for soft edges. `cos_angle` is the cosine between the surface
+    // normal and the direction towards the light. Points outside the map are lit.
+    pub fn visibility(&self, point: Vec3, cos_angle: f32) -> f32 {
+        // The cube face looking most directly at the point
+        let Some(face) = self.faces.iter().max_by(|a, b| {
+            a.forward.dot(&(point - a.origin)).total_cmp(&b.forward.dot(&(point - b.origin)))
+        }) else {
+            return 1.0;
+        };
+        let Some((texel, depth)) = face.project(point) else {
+            return 1.0;
+        };
+
+        let cos_angle = cos_angle.clamp(1e-3, 1.0);
+        let slope = ((1.0 - cos_angle * cos_angle).sqrt() / cos_angle).min(MAX_SLOPE);
+        let depth = depth - face.texel_size(depth) * (DEPTH_BIAS + SLOPE_BIAS * slope);
+
+        let mut lit = 0.0;
+        for dy in -PCF_RADIUS..=PCF_RADIUS {
+            for dx in -PCF_RADIUS..=PCF_RADIUS {
+                lit += face.compare(texel + Vec2::new(dx as f32, dy as f32), depth);
+            }
+        }
+        lit / ((PCF_RADIUS * 2 + 1) * (PCF_RADIUS * 2 + 1)) as f32
+    }
+}
+
+impl ShadowFace {
+    fn new(resolution: usize) -> Self {
+        ShadowFace {
+            origin: Vec3::zeros(),
+            right: Vec3::x(),
+            up: Vec3::y(),
+            forward: Vec3::z(),
+            perspective: false,
+            extent: 1.0,
+            resolution,
+            depth: vec![f32::INFINITY; resolution * resolution],
+        }
+    }
+
+    fn look(&mut self, origin: Vec3, forward: Vec3, perspective: bool, extent: f32, resolution: usize) {
+        let helper = if forward.y.abs() < 0.9 { Vec3::y() } else { Vec3::z() };
+        self.right = helper.cross(&forward).normalize();
+        self.up = forward.cross(&self.right);
+        self.forward = forward;
+        self.origin = origin;
+        self.perspective = perspective;
+        self.extent = extent;
+        if self.resolution != resolution {
+            self.resolution = resolution;
+            self.depth = vec![f32::INFINITY; resolution * resolution];
+        }
+    }
+
+    // Texel coordinates and depth of a world space point, if it is in front
+    // of the face and inside it
+    fn project(&self, point: Vec3) -> Option<(Vec2, f32)> {
+        let (screen, depth) = self.to_screen(point)?;
+        let inside = screen.x.abs() <= 1.0 && screen.y.abs() <= 1.0;
+        inside.then(|| ((screen * 0.5 + Vec2::repeat(0.5)) * self.resolution as f32, depth))
+    }
+
+    // Position on the face in [-1, 1] when inside, and depth along `forward`
+    fn to_screen(&self, point: Vec3) -> Option<(Vec2, f32)> {
+        let offset = point - self.origin;
+        let depth = offset.dot(&self.forward);
+        let plane = Vec2::new(offset.dot(&self.right), offset.dot(&self.up));
+        if self.perspective {
+            (depth >= NEAR_PLANE).then(|| (plane / depth, depth))
+        } else {
+            Some((plane / self.extent, depth))
+        }
+    }
+
+    // World space size of a texel at `depth`
+    fn texel_size(&self, depth: f32) -> f32 {
+        let half_width = if self.perspective { depth } else { self.extent };
+        half_width * 2.0 / self.resolution as f32
+    }
+
+    // Rasterizes a caster's triangle into the face. Triangles with a corner
+    // behind a perspective face's near plane are skipped instead of clipped:
+    // the bodies' triangles are tiny next to their distance from the star, so
+    // this only drops casters within NEAR_PLANE of it, which has none.
+    fn draw_triangle(&mut self, triangle: &[Vec3]) {
+        let mut corners = [(Vec2::zeros(), 0.0); 3];
+        for (corner, &p) in corners.iter_mut().zip(triangle) {
+            let Some((screen, depth)) = self.to_screen(p) else {
+                return;
+            };
+            *corner = ((screen * 0.5 + Vec2::repeat(0.5)) * self.resolution as f32, depth);
+        }
+        let [(a, depth_a), (b, depth_b), (c, depth_c)] = corners;
+        let area = edge(a, b, c);
+        if area == 0.0 {
+            return;
+        }
+
+        // Depth is linear across the face for orthographic faces, its
+        // reciprocal is for perspective ones
+        let values = if self.perspective {
+            [1.0 / depth_a, 1.0 / depth_b, 1.0 / depth_c]
+        } else {
+            [depth_a, depth_b, depth_c]
+        };
+
+        let size = self.resolution as f32;
+        let min_x = a.x.min(b.x).min(c.x).floor().clamp(0.0, size) as usize;
+        let max_x = a.x.max(b.x).max(c.x).ceil().clamp(0.0, size) as usize;
+        let min_y = a.y.min(b.y).min(c.y).floor().clamp(0.0, size) as usize;
+        let max_y = a.y.max(b.y).max(c.y).ceil().clamp(0.0, size) as usize;
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let w = [edge(b, c, p) / area, edge(c, a, p) / area, edge(a, b, p) / area];
+                if w.iter().any(|&w| w < 0.0) {
+                    continue;
+                }
+                let value = w[0] * values[0] + w[1] * values[1] + w[2] * values[2];
+                let depth = if self.perspective { 1.0 / value } else { value };
+                let stored = &mut self.depth[y * self.resolution + x];
+                *stored = stored.min(depth);
+            }
+        }
+    }
+
+    // Share of the four texels around `texel` whose caster is farther than
+    // `depth`, weighted by how close `texel` is to each
+    fn compare(&self, texel: Vec2, depth: f32) -> f32 {
+        let base = texel - Vec2::repeat(0.5);
+        let (x, y) = (base.x.floor(), base.y.floor());
+        let (fx, fy) = (base.x - x, base.y - y);
+
+        let last = self.resolution as i64 - 1;
+        let lit = |dx: i64, dy: i64| {
+            let tx = (x as i64 + dx).clamp(0, last) as usize;
+            let ty = (y as i64 + dy).clamp(0, last) as usize;
+            if depth <= self.depth[ty * self.resolution + tx] { 1.0 } else { 0.0 }
+        };
+
+        let top = lit(0, 0) * (1.0 - fx) + lit(1, 0) * fx;
+        let bottom = lit(0, 1) * (1.0 - fx) + lit(1, 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+fn transform(matrix: &Mat4, p: Vec3) -> Vec3 {
+    let p = matrix * Vec4::new(p.x, p.y, p.z, 1.0);
+    p.xyz() / p.w
+}
+
+fn edge(a: Vec2, b: Vec2, p: Vec2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::generate_icosphere;
+
+    // Unit icosphere scaled to `radius` at `center`
+    fn ball(center: Vec3, radius: f32) -> (Vec<Vertex>, Mat4) {
+        let matrix = Mat4::new_translation(&center) * Mat4::new_scaling(radius);
+        (generate_icosphere(3), matrix)
+    }
+
+    #[test]
+    fn point_light_casts_a_moon_shadow_with_a_soft_edge() {
+        let (moon, matrix) = ball(Vec3::new(200.0, 0.0, 0.0), 30.0);
+        let mut map = ShadowMap::default();
+        map.render(ShadowLight::Point(Vec3::zeros()), &[(&moon, matrix)]);
+
+        // Behind the moon, seen from the light, and off to the side of it
+        assert_eq!(map.visibility(Vec3::new(400.0, 0.0, 0.0), 1.0), 0.0);
+        assert_eq!(map.visibility(Vec3::new(400.0, 0.0, 200.0), 1.0), 1.0);
+        // In front of the moon, and on its own lit face
+        assert_eq!(map.visibility(Vec3::new(100.0, 0.0, 0.0), 1.0), 1.0);
+        assert_eq!(map.visibility(Vec3::new(170.0, 0.0, 0.0), 1.0), 1.0);
+
+        // The shadow's edge at twice the distance is twice the moon's radius
+        // out, and fades across a few texels
+        let edge = (1..200)
+            .map(|i| map.visibility(Vec3::new(400.0, 0.0, 50.0 + i as f32 * 0.1), 1.0))
+            .collect::<Vec<_>>();
+        let partial = edge.iter().filter(|&&v| v > 0.0 && v < 1.0).count();
+        assert!(partial > 5, "{} partially lit points", partial);
+        let half = edge.iter().position(|&v| v >= 0.5).unwrap() as f32 * 0.1 + 50.0;
+        assert!((half - 60.0).abs() < 2.0, "shadow edge at {}", half);
+    }
+
+    #[test]
+    fn directional_map_fits_the_casters() {
+        let (moon, matrix) = ball(Vec3::new(500.0, 300.0, 0.0), 20.0);
+        let mut map = ShadowMap::default();
+        map.render(ShadowLight::Directional(Vec3::new(-1.0, 0.0, 0.0)), &[(&moon, matrix)]);
+
+        assert_eq!(map.visibility(Vec3::new(600.0, 300.0, 0.0), 1.0), 0.0);
+        assert_eq!(map.visibility(Vec3::new(400.0, 300.0, 0.0), 1.0), 1.0);
+        // Outside the fitted square
+        assert_eq!(map.visibility(Vec3::new(600.0, 400.0, 0.0), 1.0), 1.0);
+    }
+}
@@ -1,6 +1,54 @@
 use nalgebra_glm::{Vec2, Vec3};
+use std::collections::HashMap;
+use std::f32::consts::PI;
 use crate::vertex::Vertex;
 
+// How a body's sphere mesh is built
+#[derive(Clone, Copy, PartialEq)]
+pub enum SphereKind {
+    // Latitude / longitude grid from `generate_sphere`
+    Uv,
+    // Subdivided icosahedron from `generate_icosphere`
+    Icosphere,
+    // Inflated cube from `generate_cube_sphere`
+    CubeSphere,
+}
+
+impl SphereKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SphereKind::Uv => "UV SPHERE",
+            SphereKind::Icosphere => "ICOSPHERE",
+            SphereKind::CubeSphere => "CUBE SPHERE",
+        }
+    }
+
+    pub fn next(&self) -> SphereKind {
+        match self {
+            SphereKind::Uv => SphereKind::Icosphere,
+            SphereKind::Icosphere => SphereKind::CubeSphere,
+            SphereKind::CubeSphere => SphereKind::Uv,
+        }
+    }
+
+    // Sphere of `radius` with about as many triangles as
+    // `generate_sphere(radius, segments)`, which has 2 * segments²
+    pub fn generate(&self, radius: f32, segments: u32) -> Vec<Vertex> {
+        let triangles = 2.0 * (segments * segments) as f32;
+        let mut vertices = match self {
+            SphereKind::Uv => return generate_sphere(radius, segments),
+            // 20 * 4^n triangles
+            SphereKind::Icosphere => generate_icosphere((triangles / 20.0).log(4.0).round().max(0.0) as u32),
+            // 12 * resolution² triangles
+            SphereKind::CubeSphere => generate_cube_sphere((triangles / 12.0).sqrt().round().max(1.0) as u32),
+        };
+        for vertex in &mut vertices {
+            vertex.position *= radius;
+        }
+        vertices
+    }
+}
+
 pub fn generate_sphere(radius: f32, segments: u32) -> Vec<Vertex> {
     let mut vertices = Vec::new();
     
@@ -56,45 +104,160 @@ pub fn generate_sphere(radius: f32, segments: u32) -> Vec<Vertex> {
     indexed_vertices
 }
 
-pub fn generate_ring(inner_radius: f32, outer_radius: f32, segments: u32) -> Vec<Vertex> {
-    let mut vertices = Vec::new();
-    
-    for i in 0..=segments {
-        let angle = (i as f32 / segments as f32) * 2.0 * std::f32::consts::PI;
-        let cos_a = angle.cos();
-        let sin_a = angle.sin();
-        
-        // Outer vertex
-        let outer_pos = Vec3::new(outer_radius * cos_a, 0.0, outer_radius * sin_a);
-        let outer_normal = Vec3::new(0.0, 1.0, 0.0);
-        let outer_tex = Vec2::new(i as f32 / segments as f32, 1.0);
-        vertices.push(Vertex::new(outer_pos, outer_normal, outer_tex));
-        
-        // Inner vertex
-        let inner_pos = Vec3::new(inner_radius * cos_a, 0.0, inner_radius * sin_a);
-        let inner_normal = Vec3::new(0.0, 1.0, 0.0);
-        let inner_tex = Vec2::new(i as f32 / segments as f32, 0.0);
-        vertices.push(Vertex::new(inner_pos, inner_normal, inner_tex));
+// Unit sphere made by splitting each face of an icosahedron into four,
+// `subdivisions` times, and pushing the new vertices out onto the sphere.
+// All triangles end up close in size and shape, with none bunched at the poles.
+pub fn generate_icosphere(subdivisions: u32) -> Vec<Vertex> {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ].iter().map(|&(x, y, z)| Vec3::new(x, y, z).normalize()).collect();
+
+    let mut faces: Vec<[usize; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges are shared by two faces, which must share the new vertex too
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push(((positions[a] + positions[b]) * 0.5).normalize());
+                positions.len() - 1
+            })
+        };
+
+        faces = faces.iter().flat_map(|&[a, b, c]| {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+        }).collect();
     }
-    
-    // Generate triangles
-    let mut indexed_vertices = Vec::new();
-    
-    for i in 0..segments {
-        let base = (i * 2) as usize;
-        let next_base = ((i + 1) * 2) as usize;
-        
-        // First triangle
-        indexed_vertices.push(vertices[base].clone());
-        indexed_vertices.push(vertices[next_base].clone());
-        indexed_vertices.push(vertices[base + 1].clone());
-        
-        // Second triangle
-        indexed_vertices.push(vertices[base + 1].clone());
-        indexed_vertices.push(vertices[next_base].clone());
-        indexed_vertices.push(vertices[next_base + 1].clone());
+
+    let mut vertices = Vec::with_capacity(faces.len() * 3);
+    for [a, b, c] in faces {
+        push_sphere_triangle(&mut vertices, [positions[a], positions[b], positions[c]]);
     }
-    
-    indexed_vertices
+    vertices
 }
 
+// Unit sphere made from a cube with each face split into a `resolution` x
+// `resolution` grid. Grid points are mapped onto the sphere with the
+// "spherified cube" formula rather than normalized, which keeps the cells
+// near the cube's corners from shrinking.
+pub fn generate_cube_sphere(resolution: u32) -> Vec<Vertex> {
+    let n = resolution.max(1);
+    // Face direction and two axes across it, with right x up = direction
+    let faces = [
+        (Vec3::x(), -Vec3::z(), Vec3::y()),
+        (-Vec3::x(), Vec3::z(), Vec3::y()),
+        (Vec3::y(), Vec3::x(), -Vec3::z()),
+        (-Vec3::y(), Vec3::x(), Vec3::z()),
+        (Vec3::z(), Vec3::x(), Vec3::y()),
+        (-Vec3::z(), -Vec3::x(), Vec3::y()),
+    ];
+
+    let mut vertices = Vec::with_capacity((6 * n * n * 6) as usize);
+    for (direction, right, up) in faces {
+        let point = |i: u32, j: u32| {
+            let s = i as f32 / n as f32 * 2.0 - 1.0;
+            let t = j as f32 / n as f32 * 2.0 - 1.0;
+            spherify(direction + right * s + up * t)
+        };
+
+        for i in 0..n {
+            for j in 0..n {
+                let (p00, p10, p11, p01) = (point(i, j), point(i + 1, j), point(i + 1, j + 1), point(i, j + 1));
+                push_sphere_triangle(&mut vertices, [p00, p10, p11]);
+                push_sphere_triangle(&mut vertices, [p00, p11, p01]);
+            }
+        }
+    }
+    vertices
+}
+
+// Point of the cube [-1, 1]³ moved onto the unit sphere
+fn spherify(p: Vec3) -> Vec3 {
+    let (x2, y2, z2) = (p.x * p.x, p.y * p.y, p.z * p.z);
+    Vec3::new(
+        p.x * (1.0 - y2 / 2.0 - z2 / 2.0 + y2 * z2 / 3.0).sqrt(),
+        p.y * (1.0 - z2 / 2.0 - x2 / 2.0 + z2 * x2 / 3.0).sqrt(),
+        p.z * (1.0 - x2 / 2.0 - y2 / 2.0 + x2 * y2 / 3.0).sqrt(),
+    )
+}
+
+// Texture coordinates laid out like `generate_sphere`'s: u goes around the
+// y axis from +x towards +z, v from +y (0) to -y (1)
+fn spherical_uv(p: &Vec3) -> Vec2 {
+    let u = p.z.atan2(p.x) / (2.0 * PI);
+    let v = p.y.clamp(-1.0, 1.0).acos() / PI;
+    Vec2::new(if u < 0.0 { u + 1.0 } else { u }, v)
+}
+
+// Adds a triangle on the unit sphere, with its texture coordinates made to
+// vary smoothly across it. A triangle crossing the u = 1 / u = 0 seam gets
+// u past 1 on that side, which textures repeat over, and a corner sitting on
+// a pole, where any u is valid, takes the u of the other two.
+fn push_sphere_triangle(vertices: &mut Vec<Vertex>, corners: [Vec3; 3]) {
+    let on_pole = corners.map(|p| p.x * p.x + p.z * p.z < 1e-10);
+    let mut uvs = corners.map(|p| spherical_uv(&p));
+
+    let max_u = (0..3).filter(|&i| !on_pole[i]).map(|i| uvs[i].x).fold(0.0, f32::max);
+    for i in 0..3 {
+        if !on_pole[i] && max_u - uvs[i].x > 0.5 {
+            uvs[i].x += 1.0;
+        }
+    }
+    for i in 0..3 {
+        if on_pole[i] {
+            uvs[i].x = (uvs[(i + 1) % 3].x + uvs[(i + 2) % 3].x) / 2.0;
+        }
+    }
+
+    for (position, tex_coords) in corners.iter().zip(uvs) {
+        vertices.push(Vertex::new(*position, *position, tex_coords));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Standard deviation of the triangle areas over their mean
+    fn area_spread(vertices: &[Vertex]) -> f32 {
+        let areas: Vec<f32> = vertices.chunks_exact(3)
+            .map(|t| (t[1].position - t[0].position).cross(&(t[2].position - t[0].position)).norm() / 2.0)
+            .collect();
+        let mean = areas.iter().sum::<f32>() / areas.len() as f32;
+        let variance = areas.iter().map(|a| (a - mean).powi(2)).sum::<f32>() / areas.len() as f32;
+        variance.sqrt() / mean
+    }
+
+    #[test]
+    fn icosphere_and_cube_sphere_triangles_are_more_even_than_uv_sphere() {
+        // About 5000 triangles each
+        let uv = generate_sphere(1.0, 50);
+        let icosphere = generate_icosphere(4);
+        let cube_sphere = generate_cube_sphere(20);
+
+        let (uv, icosphere, cube_sphere) = (area_spread(&uv), area_spread(&icosphere), area_spread(&cube_sphere));
+        assert!(icosphere < 0.1, "icosphere spread {}", icosphere);
+        assert!(cube_sphere < 0.1, "cube sphere spread {}", cube_sphere);
+        assert!(uv > 4.0 * cube_sphere.max(icosphere), "uv sphere spread {}", uv);
+    }
+
+    #[test]
+    fn texture_coordinates_do_not_jump_across_the_seam() {
+        for vertices in [generate_icosphere(3), generate_cube_sphere(8)] {
+            for triangle in vertices.chunks_exact(3) {
+                let us = triangle.iter().map(|v| v.tex_coords.x);
+                let spread = us.clone().fold(f32::MIN, f32::max) - us.fold(f32::MAX, f32::min);
+                assert!(spread < 0.5, "triangle spans {} in u", spread);
+            }
+        }
+    }
+}
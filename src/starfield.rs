@@ -0,0 +1,158 @@
+use nalgebra_glm::{Vec3, dot};
+use crate::camera::Camera;
+use crate::color::srgb_to_linear;
+use crate::fragment_shaders::fbm;
+use crate::framebuffer::Framebuffer;
+
+// Pixels between samples of the galactic band, which is interpolated in between
+const BAND_STEP: usize = 4;
+const BAND_WIDTH: f32 = 0.22;
+const BAND_COLOR: Vec3 = Vec3::new(0.012, 0.010, 0.022);
+
+// Fraction of stars that are pulled towards the plane of the galaxy
+const BAND_STAR_FRACTION: f32 = 0.4;
+
+struct Star {
+    direction: Vec3,
+    brightness: f32,
+    color: Vec3,
+}
+
+// Small xorshift generator so the sky is the same for a given seed
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng { state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn unit_vector(&mut self) -> Vec3 {
+        let z = self.next_f32() * 2.0 - 1.0;
+        let phi = self.next_f32() * 2.0 * std::f32::consts::PI;
+        let r = (1.0 - z * z).sqrt();
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+}
+
+pub struct Starfield {
+    stars: Vec<Star>,
+    band_normal: Vec3,
+}
+
+impl Starfield {
+    pub fn new(seed: u64, count: usize) -> Self {
+        let mut rng = Rng::new(seed);
+        let band_normal = Vec3::new(0.3, 1.0, 0.2).normalize();
+
+        let stars = (0..count).map(|_| {
+            let mut direction = rng.unit_vector();
+
+            if rng.next_f32() < BAND_STAR_FRACTION {
+                // Squash towards the band plane
+                let height = dot(&direction, &band_normal);
+                direction = (direction - band_normal * height * 0.85).normalize();
+            }
+
+            // Most stars are faint, a handful are bright (linear intensity)
+            let brightness = 0.02 + 0.98 * rng.next_f32().powf(6.0);
+
+            // Pick a stellar temperature: blue-white, white, yellow, orange or red
+            let color = srgb_to_linear(match rng.next_f32() {
+                t if t < 0.10 => Vec3::new(0.65, 0.75, 1.0),
+                t if t < 0.55 => Vec3::new(1.0, 1.0, 1.0),
+                t if t < 0.80 => Vec3::new(1.0, 0.93, 0.75),
+                t if t < 0.95 => Vec3::new(1.0, 0.78, 0.55),
+                _ => Vec3::new(1.0, 0.6, 0.5),
+            });
+
+            Star { direction, brightness, color }
+        }).collect();
+
+        Starfield { stars, band_normal }
+    }
+
+    pub fn render(&self, framebuffer: &mut Framebuffer, camera: &Camera) {
+        self.render_band(framebuffer, camera);
+        self.render_stars(framebuffer, camera);
+    }
+
+    fn band_intensity(&self, direction: &Vec3) -> f32 {
+        let height = dot(direction, &self.band_normal) / BAND_WIDTH;
+        let falloff = (-height * height).exp();
+        let clouds = fbm(direction * 4.0, 4);
+        falloff * (0.35 + 0.65 * clouds)
+    }
+
+    fn render_band(&self, framebuffer: &mut Framebuffer, camera: &Camera) {
+        let width = framebuffer.width;
+        let height = framebuffer.height;
+        let grid_w = width / BAND_STEP + 2;
+        let grid_h = height / BAND_STEP + 2;
+
+        let rays = camera.rays(width, height);
+        let mut grid = Vec::with_capacity(grid_w * grid_h);
+        for gy in 0..grid_h {
+            for gx in 0..grid_w {
+                let x = (gx * BAND_STEP) as f32;
+                let y = (gy * BAND_STEP) as f32;
+                let direction = rays.direction(x, y);
+                grid.push(self.band_intensity(&direction));
+            }
+        }
+
+        for y in 0..height {
+            let gy = y / BAND_STEP;
+            let ty = (y % BAND_STEP) as f32 / BAND_STEP as f32;
+
+            for x in 0..width {
+                let gx = x / BAND_STEP;
+                let tx = (x % BAND_STEP) as f32 / BAND_STEP as f32;
+
+                let top = grid[gy * grid_w + gx] * (1.0 - tx) + grid[gy * grid_w + gx + 1] * tx;
+                let bottom = grid[(gy + 1) * grid_w + gx] * (1.0 - tx) + grid[(gy + 1) * grid_w + gx + 1] * tx;
+                let intensity = top * (1.0 - ty) + bottom * ty;
+
+                framebuffer.add_light(x, y, BAND_COLOR * intensity);
+            }
+        }
+    }
+
+    fn render_stars(&self, framebuffer: &mut Framebuffer, camera: &Camera) {
+        let width = framebuffer.width;
+        let height = framebuffer.height;
+
+        for star in &self.stars {
+            let Some((sx, sy)) = camera.project_direction(&star.direction, width, height) else {
+                continue;
+            };
+            if sx < 0.0 || sy < 0.0 {
+                continue;
+            }
+
+            let (x, y) = (sx as usize, sy as usize);
+            framebuffer.add_light(x, y, star.color * star.brightness);
+
+            // Bright stars bleed into their neighbours
+            if star.brightness > 0.25 {
+                let halo = star.color * (star.brightness * 0.1);
+                framebuffer.add_light(x + 1, y, halo);
+                framebuffer.add_light(x, y + 1, halo);
+                if x > 0 {
+                    framebuffer.add_light(x - 1, y, halo);
+                }
+                if y > 0 {
+                    framebuffer.add_light(x, y - 1, halo);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use nalgebra_glm::{Vec2, Vec3};
+use std::io::Result;
+use std::path::Path;
+use crate::image::Image;
+
+// What happens to texture coordinates outside 0.0 - 1.0
+#[derive(Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    // Edge texels stretch outwards, for MTL maps with `-clamp on`
+    Clamp,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Filter {
+    // Closest texel of the full size image
+    Nearest,
+    // Blend of the four closest texels of the full size image
+    Bilinear,
+    // Bilinear lookups in the two mip levels around the LOD, blended
+    Trilinear,
+}
+
+impl Filter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Nearest => "NEAREST",
+            Filter::Bilinear => "BILINEAR",
+            Filter::Trilinear => "TRILINEAR",
+        }
+    }
+
+    pub fn next(&self) -> Filter {
+        match self {
+            Filter::Nearest => Filter::Bilinear,
+            Filter::Bilinear => Filter::Trilinear,
+            Filter::Trilinear => Filter::Nearest,
+        }
+    }
+}
+
+// Image with a mip chain for sampling from fragment shaders. Texture
+// coordinates run left to right and top to bottom over the image.
+pub struct Texture {
+    // Level 0 is the full image, each next one half the size down to 1x1
+    levels: Vec<Image>,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+impl Texture {
+    // Takes an image in linear color, with at least one pixel
+    pub fn new(image: Image) -> Self {
+        assert!(image.width > 0 && image.height > 0, "texture image has no pixels");
+        Texture {
+            levels: generate_mipmaps(image),
+            wrap: WrapMode::Repeat,
+            filter: Filter::Trilinear,
+        }
+    }
+
+    // Loads a PPM, TGA or PNG file and converts it to linear color
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Texture::new(Image::load(path)?.into_linear()))
+    }
+
+    // Magenta and black squares standing in for a texture that could not be
+    // found or loaded, loud enough to notice on any model
+    pub fn checkerboard() -> Self {
+        const SIZE: usize = 64;
+        const SQUARE: usize = 8;
+        let magenta = Vec3::new(1.0, 0.0, 1.0);
+        let pixels = (0..SIZE * SIZE)
+            .map(|i| {
+                let (x, y) = (i % SIZE / SQUARE, i / SIZE / SQUARE);
+                if (x + y).is_multiple_of(2) { magenta } else { Vec3::zeros() }
+            })
+            .collect();
+        Texture::new(Image::new(SIZE, SIZE, pixels))
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    // Mip level whose texels are about one pixel in size, given how much the
+    // texture coordinates change per pixel in screen x and y
+    pub fn lod(&self, duv_dx: Vec2, duv_dy: Vec2) -> f32 {
+        let size = Vec2::new(self.width() as f32, self.height() as f32);
+        let dx = duv_dx.component_mul(&size);
+        let dy = duv_dy.component_mul(&size);
+        let rho = dx.norm().max(dy.norm());
+        if rho > 0.0 { rho.log2() } else { 0.0 }
+    }
+
+    // Filtered lookup, with the LOD taken from screen-space derivatives of `uv`
+    pub fn sample(&self, uv: Vec2, duv_dx: Vec2, duv_dy: Vec2) -> Vec3 {
+        match self.filter {
+            Filter::Nearest => self.nearest(0, uv),
+            Filter::Bilinear => self.bilinear(0, uv),
+            Filter::Trilinear => self.sample_level(uv, self.lod(duv_dx, duv_dy)),
+        }
+    }
+
+    // Trilinear lookup at an explicit LOD
+    pub fn sample_level(&self, uv: Vec2, lod: f32) -> Vec3 {
+        let lod = lod.clamp(0.0, (self.levels.len() - 1) as f32);
+        let level = lod.floor() as usize;
+        let t = lod - level as f32;
+
+        let color = self.bilinear(level, uv);
+        if t > 0.0 {
+            color * (1.0 - t) + self.bilinear(level + 1, uv) * t
+        } else {
+            color
+        }
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Vec3 {
+        let image = &self.levels[level];
+        let (width, height) = (image.width as i64, image.height as i64);
+        let (x, y) = match self.wrap {
+            WrapMode::Repeat => (x.rem_euclid(width), y.rem_euclid(height)),
+            WrapMode::Clamp => (x.clamp(0, width - 1), y.clamp(0, height - 1)),
+        };
+        image.pixel(x as usize, y as usize)
+    }
+
+    fn nearest(&self, level: usize, uv: Vec2) -> Vec3 {
+        let image = &self.levels[level];
+        let x = (uv.x * image.width as f32).floor() as i64;
+        let y = (uv.y * image.height as f32).floor() as i64;
+        self.texel(level, x, y)
+    }
+
+    fn bilinear(&self, level: usize, uv: Vec2) -> Vec3 {
+        let image = &self.levels[level];
+        // Texel centers sit at +0.5
+        let x = uv.x * image.width as f32 - 0.5;
+        let y = uv.y * image.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(level, x0, y0) * (1.0 - tx) + self.texel(level, x0 + 1, y0) * tx;
+        let bottom = self.texel(level, x0, y0 + 1) * (1.0 - tx) + self.texel(level, x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+// Halves the image with a 2x2 box filter until it is 1x1. An odd row or
+// column is folded into the last texel of the smaller level.
+fn generate_mipmaps(image: Image) -> Vec<Image> {
+    let mut levels = vec![image];
+
+    loop {
+        let previous = levels.last().unwrap();
+        if previous.width <= 1 && previous.height <= 1 {
+            break;
+        }
+
+        let width = (previous.width / 2).max(1);
+        let height = (previous.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            let rows = source_range(y, height, previous.height);
+            for x in 0..width {
+                let columns = source_range(x, width, previous.width);
+                let mut sum = Vec3::zeros();
+                for sy in rows.clone() {
+                    for sx in columns.clone() {
+                        sum += previous.pixel(sx, sy);
+                    }
+                }
+                pixels.push(sum / (rows.len() * columns.len()) as f32);
+            }
+        }
+
+        levels.push(Image::new(width, height, pixels));
+    }
+
+    levels
+}
+
+// Source texels averaged into texel `i` of a level `size` wide, from one
+// `source_size` wide
+fn source_range(i: usize, size: usize, source_size: usize) -> std::ops::Range<usize> {
+    let start = i * source_size / size;
+    let end = if i + 1 == size { source_size } else { (i + 1) * source_size / size };
+    start..end.max(start + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(width: usize, height: usize) -> Image {
+        let pixels = (0..width * height)
+            .map(|i| if (i % width + i / width).is_multiple_of(2) { Vec3::repeat(1.0) } else { Vec3::zeros() })
+            .collect();
+        Image::new(width, height, pixels)
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one_texel() {
+        let texture = Texture::new(checkerboard(10, 3));
+        let sizes: Vec<(usize, usize)> = texture.levels.iter().map(|l| (l.width, l.height)).collect();
+
+        assert_eq!(sizes, vec![(10, 3), (5, 1), (2, 1), (1, 1)]);
+        // Box filtering keeps the average brightness
+        let last = texture.levels.last().unwrap().pixel(0, 0);
+        assert!((last.x - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    #[should_panic(expected = "no pixels")]
+    fn empty_image_is_not_a_texture() {
+        Texture::new(Image::new(0, 0, Vec::new()));
+    }
+
+    #[test]
+    fn lod_follows_texels_per_pixel() {
+        let texture = Texture::new(checkerboard(64, 64));
+        let texel = 1.0 / 64.0;
+
+        assert_eq!(texture.lod(Vec2::new(texel, 0.0), Vec2::new(0.0, texel)), 0.0);
+        assert_eq!(texture.lod(Vec2::new(4.0 * texel, 0.0), Vec2::new(0.0, texel)), 2.0);
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let pixels = vec![Vec3::zeros(), Vec3::repeat(1.0)];
+        let mut texture = Texture::new(Image::new(2, 1, pixels));
+        texture.filter = Filter::Nearest;
+        let flat = Vec2::zeros();
+
+        texture.wrap = WrapMode::Repeat;
+        assert_eq!(texture.sample(Vec2::new(1.25, 0.5), flat, flat), Vec3::zeros());
+        texture.wrap = WrapMode::Clamp;
+        assert_eq!(texture.sample(Vec2::new(1.25, 0.5), flat, flat), Vec3::repeat(1.0));
+    }
+
+    #[test]
+    fn trilinear_minification_averages_detail() {
+        let texture = Texture::new(checkerboard(64, 64));
+        let step = Vec2::new(16.0 / 64.0, 0.0);
+
+        let color = texture.sample(Vec2::new(0.3, 0.7), step, step.yx());
+        assert!((color.x - 0.5).abs() < 1e-4);
+    }
+}
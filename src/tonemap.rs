@@ -0,0 +1,60 @@
+use nalgebra_glm::Vec3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+    // Plain clamp to 0.0 - 1.0, highlights clip to white
+    Clamp,
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+impl ToneMapping {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapping::Clamp => "CLAMP",
+            ToneMapping::Reinhard => "REINHARD",
+            ToneMapping::Aces => "ACES",
+        }
+    }
+
+    pub fn next(&self) -> ToneMapping {
+        match self {
+            ToneMapping::Clamp => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::Clamp,
+        }
+    }
+}
+
+pub struct ToneMapSettings {
+    pub operator: ToneMapping,
+    // Linear multiplier applied before the curve
+    pub exposure: f32,
+}
+
+impl Default for ToneMapSettings {
+    fn default() -> Self {
+        ToneMapSettings {
+            operator: ToneMapping::Aces,
+            exposure: 1.0,
+        }
+    }
+}
+
+// Maps a linear HDR color into the 0.0 - 1.0 range, still linear
+pub fn tone_map(color: Vec3, settings: &ToneMapSettings) -> Vec3 {
+    let color = color * settings.exposure;
+
+    match settings.operator {
+        ToneMapping::Clamp => color.map(|c| c.clamp(0.0, 1.0)),
+        ToneMapping::Reinhard => color.map(|c| {
+            let c = c.max(0.0);
+            c / (1.0 + c)
+        }),
+        ToneMapping::Aces => color.map(|c| {
+            let c = c.max(0.0);
+            ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)
+        }),
+    }
+}
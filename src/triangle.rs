@@ -1,73 +1,222 @@
 use nalgebra_glm::{Vec2, Vec3};
 
-use crate::fragment::Fragment;
+use crate::fragment::{Fragment, MAX_SAMPLES};
+use crate::framebuffer::Rect;
 use crate::vertex::Vertex;
-use crate::line::line;
-use crate::color::Color;
-use crate::fragment_shaders::FragmentShader;
+use crate::line::{line_with_style, LineStyle};
+use crate::fragment_shaders::{FragmentShader, ShaderInput};
+use crate::Uniforms;
 
-pub fn _triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec<Fragment> {
+// Outline of the triangle, used by the wireframe overlay
+pub fn wireframe_triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex, style: &LineStyle, clip: &Rect) -> Vec<Fragment> {
   let mut fragments = Vec::new();
 
   // Draw the three sides of the triangle
-  fragments.extend(line(v1, v2));
-  fragments.extend(line(v2, v3));
-  fragments.extend(line(v3, v1));
+  fragments.extend(line_with_style(v1, v2, style, clip));
+  fragments.extend(line_with_style(v2, v3, style, clip));
+  fragments.extend(line_with_style(v3, v1, style, clip));
 
   fragments
 }
 
-pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec<Fragment> {
-  triangle_with_shader(v1, v2, v3, |_, _, _, _, _, _| Color::new(100, 100, 100))
+
+// Vertex positions are snapped to a fixed-point grid of 1/256 pixel so that
+// edge tests are exact and two triangles sharing an edge agree on every sample
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_SCALE: f32 = (1 << SUBPIXEL_BITS) as f32;
+
+#[derive(Clone, Copy)]
+struct FixedPoint {
+  x: i64,
+  y: i64,
+}
+
+// Largest screen coordinate, in pixels, a vertex may have. Snapped
+// coordinates then stay below 2^30, so the products in `edge_function`
+// can't overflow an i64, and no float reaches the saturating `as` cast.
+const GUARD_BAND: f32 = (1 << 22) as f32;
+
+// None for vertices outside the guard band or not finite
+fn snap(v: &Vec3) -> Option<FixedPoint> {
+  if !(v.x.abs() <= GUARD_BAND && v.y.abs() <= GUARD_BAND) {
+    return None;
+  }
+  Some(FixedPoint {
+    x: (v.x * SUBPIXEL_SCALE).round() as i64,
+    y: (v.y * SUBPIXEL_SCALE).round() as i64,
+  })
+}
+
+// Samples of one pixel inside the triangle
+#[derive(Clone, Copy)]
+struct Coverage {
+  coverage: u8,
+  sample_depths: [f32; MAX_SAMPLES],
+  // Barycentric weights of the first covered sample
+  covered_weights: (f32, f32, f32),
+}
+
+// Vertex attributes interpolated at one point of the triangle
+#[derive(Clone, Copy)]
+struct Attributes {
+  // Object space position
+  position: Vec3,
+  // Not normalized, so differences between pixels stay linear
+  normal: Vec3,
+  tex_coords: Vec2,
+}
+
+impl Attributes {
+  fn interpolate(v1: &Vertex, v2: &Vertex, v3: &Vertex, (w1, w2, w3): (f32, f32, f32)) -> Self {
+    Attributes {
+      position: v1.position * w1 + v2.position * w2 + v3.position * w3,
+      normal: v1.transformed_normal * w1 + v2.transformed_normal * w2 + v3.transformed_normal * w3,
+      tex_coords: v1.tex_coords * w1 + v2.tex_coords * w2 + v3.tex_coords * w3,
+    }
+  }
+
+  fn difference(&self, other: &Attributes) -> Attributes {
+    Attributes {
+      position: self.position - other.position,
+      normal: self.normal - other.normal,
+      tex_coords: self.tex_coords - other.tex_coords,
+    }
+  }
 }
 
-pub fn triangle_with_shader(v1: &Vertex, v2: &Vertex, v3: &Vertex, fragment_shader: FragmentShader) -> Vec<Fragment> {
+// Rasterizes the triangle, testing coverage at every position in `sample_positions`
+// (relative to the pixel's top-left corner). The shader runs once per covered pixel,
+// with derivatives of its inputs taken across the 2x2 quad the pixel belongs to.
+// Samples exactly on an edge belong to the triangle only if it is a top or left
+// edge, so pixels along an edge shared by two triangles are drawn exactly once.
+// Only pixels inside `clip` (the framebuffer's viewport/scissor) are visited.
+// Triangles with a vertex past the guard band are dropped whole.
+pub fn triangle_with_shader(v1: &Vertex, v2: &Vertex, v3: &Vertex, fragment_shader: FragmentShader, uniforms: &Uniforms, sample_positions: &[Vec2], clip: &Rect) -> Vec<Fragment> {
   let mut fragments = Vec::new();
-  let (a, b, c) = (v1.transformed_position, v2.transformed_position, v3.transformed_position);
-
-  let (min_x, min_y, max_x, max_y) = calculate_bounding_box(&a, &b, &c);
-
-  let triangle_area = edge_function(&a, &b, &c);
-
-  // Iterate over each pixel in the bounding box
-  for y in min_y..=max_y {
-    for x in min_x..=max_x {
-      let point = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, 0.0);
-
-      // Calculate barycentric coordinates
-      let (w1, w2, w3) = barycentric_coordinates(&point, &a, &b, &c, triangle_area);
-
-      // Check if the point is inside the triangle
-      if w1 >= 0.0 && w1 <= 1.0 && 
-         w2 >= 0.0 && w2 <= 1.0 &&
-         w3 >= 0.0 && w3 <= 1.0 {
-        // Interpolate normal
-        let normal = Vec3::new(
-            v1.transformed_normal.x * w1 + v2.transformed_normal.x * w2 + v3.transformed_normal.x * w3,
-            v1.transformed_normal.y * w1 + v2.transformed_normal.y * w2 + v3.transformed_normal.y * w3,
-            v1.transformed_normal.z * w1 + v2.transformed_normal.z * w2 + v3.transformed_normal.z * w3
-        ).normalize();
-        
-        // Interpolate position (world space)
-        let position = Vec3::new(
-            v1.position.x * w1 + v2.position.x * w2 + v3.position.x * w3,
-            v1.position.y * w1 + v2.position.y * w2 + v3.position.y * w3,
-            v1.position.z * w1 + v2.position.z * w2 + v3.position.z * w3
-        );
-        
-        // Interpolate texture coordinates
-        let tex_coords = Vec2::new(
-            v1.tex_coords.x * w1 + v2.tex_coords.x * w2 + v3.tex_coords.x * w3,
-            v1.tex_coords.y * w1 + v2.tex_coords.y * w2 + v3.tex_coords.y * w3
-        );
 
-        // Use fragment shader to calculate color
-        let color = fragment_shader(v1, v2, v3, position, normal, tex_coords);
+  let (mut v2, mut v3) = (v2, v3);
+  let (Some(a), Some(mut b), Some(mut c)) = (
+    snap(&v1.transformed_position),
+    snap(&v2.transformed_position),
+    snap(&v3.transformed_position),
+  ) else {
+    return fragments;
+  };
+
+  let mut triangle_area = edge_function(&a, &b, &c);
+
+  // Degenerate triangles cover nothing
+  if triangle_area == 0 {
+    return fragments;
+  }
+
+  // Use one winding for every triangle so the inside test and fill rule are the same
+  if triangle_area < 0 {
+    std::mem::swap(&mut v2, &mut v3);
+    std::mem::swap(&mut b, &mut c);
+    triangle_area = -triangle_area;
+  }
+
+  let area = triangle_area as f32;
+  let (za, zb, zc) = (v1.transformed_position.z, v2.transformed_position.z, v3.transformed_position.z);
+  let biases = [edge_bias(&b, &c), edge_bias(&c, &a), edge_bias(&a, &b)];
 
-        // Interpolate depth
-        let depth = a.z * w1 + b.z * w2 + c.z * w3;
+  let sample_offsets: Vec<FixedPoint> = sample_positions.iter()
+    .take(MAX_SAMPLES)
+    .map(|offset| FixedPoint {
+      x: (offset.x * SUBPIXEL_SCALE).round() as i64,
+      y: (offset.y * SUBPIXEL_SCALE).round() as i64,
+    })
+    .collect();
 
-        fragments.push(Fragment::new(x as f32, y as f32, color, depth));
+  let (min_x, min_y, max_x, max_y) = calculate_bounding_box(&a, &b, &c, clip);
+  if min_x > max_x || min_y > max_y {
+    return fragments;
+  }
+
+  let half = 1 << (SUBPIXEL_BITS - 1);
+
+  // Pixels are shaded in 2x2 quads aligned to even coordinates. Every pixel of
+  // a quad is interpolated, covered or not, so derivatives can be taken as
+  // differences between neighbours the way GPUs compute dFdx and dFdy.
+  for quad_y in ((min_y & !1)..=max_y).step_by(2) {
+    for quad_x in ((min_x & !1)..=max_x).step_by(2) {
+      let mut lanes: [Option<Coverage>; 4] = [None; 4];
+
+      for (i, lane) in lanes.iter_mut().enumerate() {
+        let (x, y) = (quad_x + (i % 2) as i64, quad_y + (i / 2) as i64);
+        if x < min_x || x > max_x || y < min_y || y > max_y {
+          continue;
+        }
+        let pixel = FixedPoint { x: x << SUBPIXEL_BITS, y: y << SUBPIXEL_BITS };
+
+        // Test every sample of the pixel
+        let mut coverage = 0u8;
+        let mut sample_depths = [f32::INFINITY; MAX_SAMPLES];
+        let mut first_covered = None;
+
+        for (sample, offset) in sample_offsets.iter().enumerate() {
+          let point = FixedPoint { x: pixel.x + offset.x, y: pixel.y + offset.y };
+          let edges = edge_values(&point, &a, &b, &c);
+
+          if is_inside(&edges, &biases) {
+            let (w1, w2, w3) = barycentric_coordinates(&edges, area);
+            coverage |= 1 << sample;
+            sample_depths[sample] = za * w1 + zb * w2 + zc * w3;
+            first_covered.get_or_insert((w1, w2, w3));
+          }
+        }
+
+        if let Some(covered_weights) = first_covered {
+          *lane = Some(Coverage { coverage, sample_depths, covered_weights });
+        }
+      }
+
+      if lanes.iter().all(|lane| lane.is_none()) {
+        continue;
+      }
+
+      // Attributes at the four pixel centers, extrapolated for pixels outside
+      // the triangle
+      let centers = [0, 1, 2, 3].map(|i| {
+        let center = FixedPoint {
+          x: ((quad_x + (i % 2) as i64) << SUBPIXEL_BITS) + half,
+          y: ((quad_y + (i / 2) as i64) << SUBPIXEL_BITS) + half,
+        };
+        edge_values(&center, &a, &b, &c)
+      });
+      let attributes = centers.map(|edges| Attributes::interpolate(v1, v2, v3, barycentric_coordinates(&edges, area)));
+
+      for (i, lane) in lanes.iter().enumerate() {
+        let Some(lane) = lane else {
+          continue;
+        };
+        let (column, row) = (i % 2, i / 2);
+        let dx = attributes[row * 2 + 1].difference(&attributes[row * 2]);
+        let dy = attributes[column + 2].difference(&attributes[column]);
+
+        // Shade at the pixel center, or at a covered sample when the center
+        // falls outside so attributes are never extrapolated
+        let value = if is_inside(&centers[i], &biases) {
+          attributes[i]
+        } else {
+          Attributes::interpolate(v1, v2, v3, lane.covered_weights)
+        };
+
+        // Use fragment shader to calculate color
+        let input = ShaderInput {
+          position: value.position,
+          normal: value.normal.normalize(),
+          tex_coords: value.tex_coords,
+          dpos_dx: dx.position,
+          dpos_dy: dy.position,
+          duv_dx: dx.tex_coords,
+          duv_dy: dy.tex_coords,
+        };
+        let color = fragment_shader(&input, uniforms);
+
+        let (x, y) = (quad_x + column as i64, quad_y + row as i64);
+        fragments.push(Fragment::with_coverage(x as f32, y as f32, color, lane.coverage, lane.sample_depths));
       }
     }
   }
@@ -75,25 +224,250 @@ pub fn triangle_with_shader(v1: &Vertex, v2: &Vertex, v3: &Vertex, fragment_shad
   fragments
 }
 
-fn calculate_bounding_box(v1: &Vec3, v2: &Vec3, v3: &Vec3) -> (i32, i32, i32, i32) {
-    let min_x = v1.x.min(v2.x).min(v3.x).floor() as i32;
-    let min_y = v1.y.min(v2.y).min(v3.y).floor() as i32;
-    let max_x = v1.x.max(v2.x).max(v3.x).ceil() as i32;
-    let max_y = v1.y.max(v2.y).max(v3.y).ceil() as i32;
+// With the winding used above, left edges point down the screen and top edges
+// point left. Samples on any other edge are pushed outside by one unit.
+fn edge_bias(from: &FixedPoint, to: &FixedPoint) -> i64 {
+  let dx = to.x - from.x;
+  let dy = to.y - from.y;
+  let is_left = dy > 0;
+  let is_top = dy == 0 && dx < 0;
+  if is_left || is_top { 0 } else { -1 }
+}
+
+fn edge_values(p: &FixedPoint, a: &FixedPoint, b: &FixedPoint, c: &FixedPoint) -> [i64; 3] {
+  [edge_function(b, c, p), edge_function(c, a, p), edge_function(a, b, p)]
+}
+
+fn is_inside(edges: &[i64; 3], biases: &[i64; 3]) -> bool {
+  edges.iter().zip(biases.iter()).all(|(edge, bias)| edge + bias >= 0)
+}
+
+// Inclusive pixel bounds of the triangle clipped to `clip`. The result is
+// empty (min > max) when the triangle lies entirely outside it.
+fn calculate_bounding_box(v1: &FixedPoint, v2: &FixedPoint, v3: &FixedPoint, clip: &Rect) -> (i64, i64, i64, i64) {
+    let round_up = (1 << SUBPIXEL_BITS) - 1;
+    let min_x = (v1.x.min(v2.x).min(v3.x) >> SUBPIXEL_BITS).max(clip.x as i64);
+    let min_y = (v1.y.min(v2.y).min(v3.y) >> SUBPIXEL_BITS).max(clip.y as i64);
+    let max_x = ((v1.x.max(v2.x).max(v3.x) + round_up) >> SUBPIXEL_BITS).min((clip.x + clip.width) as i64 - 1);
+    let max_y = ((v1.y.max(v2.y).max(v3.y) + round_up) >> SUBPIXEL_BITS).min((clip.y + clip.height) as i64 - 1);
 
     (min_x, min_y, max_x, max_y)
 }
 
-fn barycentric_coordinates(p: &Vec3, a: &Vec3, b: &Vec3, c: &Vec3, area: f32) -> (f32, f32, f32) {
-    let w1 = edge_function(b, c, p) / area;
-    let w2 = edge_function(c, a, p) / area;
-    let w3 = edge_function(a, b, p) / area;
+fn barycentric_coordinates(edges: &[i64; 3], area: f32) -> (f32, f32, f32) {
+    let w1 = edges[0] as f32 / area;
+    let w2 = edges[1] as f32 / area;
+    let w3 = edges[2] as f32 / area;
 
     (w1, w2, w3)
 }
 
-fn edge_function(a: &Vec3, b: &Vec3, c: &Vec3) -> f32 {
+// Twice the signed area of (a, b, c), in squared subpixel units
+fn edge_function(a: &FixedPoint, b: &FixedPoint, c: &FixedPoint) -> i64 {
     (c.x - a.x) * (b.y - a.y) - (c.y - a.y) * (b.x - a.x)
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nalgebra_glm::Mat4;
 
+  const SIZE: usize = 64;
+
+  fn vertex(x: f32, y: f32) -> Vertex {
+    Vertex::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, -1.0), Vec2::new(0.0, 0.0))
+  }
+
+  fn uniforms() -> Uniforms<'static> {
+    Uniforms::new(Mat4::identity(), Mat4::identity())
+  }
+
+  fn coverage_counts(triangles: &[[Vertex; 3]], sample_positions: &[Vec2]) -> Vec<u32> {
+    let mut counts = vec![0; SIZE * SIZE * sample_positions.len()];
+    for [v1, v2, v3] in triangles {
+      let clip = Rect::new(0, 0, SIZE, SIZE);
+      for fragment in triangle_with_shader(v1, v2, v3, |_, _| Vec3::zeros(), &uniforms(), sample_positions, &clip) {
+        let pixel = fragment.position.y as usize * SIZE + fragment.position.x as usize;
+        for sample in 0..sample_positions.len() {
+          if fragment.coverage & (1 << sample) != 0 {
+            counts[pixel * sample_positions.len() + sample] += 1;
+          }
+        }
+      }
+    }
+    counts
+  }
+
+  // Grid of quads split into two triangles each, with interior vertices nudged
+  // around so edges cross pixel centers at awkward angles. Grid lines fall on
+  // integer and half-integer coordinates to hit samples exactly.
+  fn tessellated_quad(flip_winding: bool) -> Vec<[Vertex; 3]> {
+    let cells = 6;
+    let (x0, y0, step) = (8.0, 10.5, 7.5);
+
+    let point = |i: usize, j: usize| {
+      let interior = i > 0 && i < cells && j > 0 && j < cells;
+      let jitter = if interior { ((i * 7 + j * 3) % 5) as f32 * 0.37 - 0.74 } else { 0.0 };
+      (x0 + i as f32 * step + jitter, y0 + j as f32 * step - jitter * 0.5)
+    };
+
+    let mut triangles = Vec::new();
+    for j in 0..cells {
+      for i in 0..cells {
+        let (ax, ay) = point(i, j);
+        let (bx, by) = point(i + 1, j);
+        let (cx, cy) = point(i, j + 1);
+        let (dx, dy) = point(i + 1, j + 1);
+
+        let mut first = [vertex(ax, ay), vertex(bx, by), vertex(dx, dy)];
+        let mut second = [vertex(ax, ay), vertex(dx, dy), vertex(cx, cy)];
+        if flip_winding {
+          first.swap(1, 2);
+          second.swap(1, 2);
+        }
+        triangles.push(first);
+        triangles.push(second);
+      }
+    }
+    triangles
+  }
+
+  #[test]
+  fn shared_edges_are_covered_exactly_once() {
+    let center = [Vec2::new(0.5, 0.5)];
+
+    for flip_winding in [false, true] {
+      let counts = coverage_counts(&tessellated_quad(flip_winding), &center);
+
+      // The quad spans x 8.0..53.0 and y 10.5..55.5
+      for y in 0..SIZE {
+        for x in 0..SIZE {
+          let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+          let inside = px > 8.0 && px < 53.0 && py > 10.5 && py < 55.5;
+          let count = counts[y * SIZE + x];
+
+          assert!(count <= 1, "pixel ({}, {}) covered {} times", x, y, count);
+          if inside {
+            assert_eq!(count, 1, "pixel ({}, {}) inside the quad was not covered", x, y);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn shared_edges_are_covered_exactly_once_per_sample() {
+    let samples: Vec<Vec2> = [(-2, -6), (6, -2), (-6, 2), (2, 6)].iter()
+      .map(|(x, y)| Vec2::new(0.5 + *x as f32 / 16.0, 0.5 + *y as f32 / 16.0))
+      .collect();
+    let counts = coverage_counts(&tessellated_quad(false), &samples);
+
+    assert!(counts.iter().all(|count| *count <= 1));
+  }
+
+  #[test]
+  fn triangle_fan_around_a_pixel_center_covers_it_once() {
+    // Eight triangles meeting at the center of pixel (20, 20)
+    let center = vertex(20.5, 20.5);
+    let ring: Vec<Vertex> = (0..8)
+      .map(|i| {
+        let angle = i as f32 / 8.0 * std::f32::consts::TAU;
+        vertex(20.5 + 10.0 * angle.cos(), 20.5 + 10.0 * angle.sin())
+      })
+      .collect();
+    let triangles: Vec<[Vertex; 3]> = (0..8)
+      .map(|i| [center.clone(), ring[i].clone(), ring[(i + 1) % 8].clone()])
+      .collect();
+
+    let counts = coverage_counts(&triangles, &[Vec2::new(0.5, 0.5)]);
+
+    assert_eq!(counts[20 * SIZE + 20], 1);
+    assert!(counts.iter().all(|count| *count <= 1));
+  }
+
+  #[test]
+  fn quad_derivatives_match_the_uv_gradient() {
+    // u runs 0..1 across 40 pixels in x, v 0..1 across 20 pixels in y
+    let uv_vertex = |x: f32, y: f32| {
+      Vertex::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, -1.0), Vec2::new((x - 3.0) / 40.0, (y - 5.0) / 20.0))
+    };
+    let fragments = triangle_with_shader(
+      &uv_vertex(3.0, 5.0),
+      &uv_vertex(43.0, 5.0),
+      &uv_vertex(3.0, 25.0),
+      |input, _| Vec3::new(input.duv_dx.x, input.duv_dy.y, input.duv_dx.y + input.duv_dy.x),
+      &uniforms(),
+      &[Vec2::new(0.5, 0.5)],
+      &Rect::new(0, 0, SIZE, SIZE),
+    );
+
+    assert!(!fragments.is_empty());
+    for fragment in &fragments {
+      assert!((fragment.color - Vec3::new(1.0 / 40.0, 1.0 / 20.0, 0.0)).norm() < 1e-5);
+    }
+  }
+
+  #[test]
+  fn triangles_past_the_guard_band_are_dropped() {
+    for far in [1e12, f32::INFINITY, f32::NAN] {
+      let fragments = triangle_with_shader(
+        &vertex(5.5, 5.5),
+        &vertex(far, 10.5),
+        &vertex(5.5, 15.5),
+        |_, _| Vec3::zeros(),
+        &uniforms(),
+        &[Vec2::new(0.5, 0.5)],
+        &Rect::new(0, 0, SIZE, SIZE),
+      );
+      assert!(fragments.is_empty());
+    }
+  }
+
+  #[test]
+  fn degenerate_triangle_covers_nothing() {
+    let fragments = triangle_with_shader(
+      &vertex(5.5, 5.5),
+      &vertex(10.5, 10.5),
+      &vertex(15.5, 15.5),
+      |_, _| Vec3::zeros(),
+      &uniforms(),
+      &[Vec2::new(0.5, 0.5)],
+      &Rect::new(0, 0, SIZE, SIZE),
+    );
+
+    assert!(fragments.is_empty());
+  }
+
+  #[test]
+  fn fragments_stay_inside_the_clip_rect() {
+    // Reaches well past every side of the scissor, including negative coordinates
+    let clip = Rect::new(10, 20, 16, 8);
+    let fragments = triangle_with_shader(
+      &vertex(-40.0, -30.0),
+      &vertex(90.0, -10.0),
+      &vertex(20.0, 100.0),
+      |_, _| Vec3::zeros(),
+      &uniforms(),
+      &[Vec2::new(0.5, 0.5)],
+      &clip,
+    );
+
+    assert_eq!(fragments.len(), clip.width * clip.height);
+    assert!(fragments.iter().all(|f| clip.contains(f.position.x as usize, f.position.y as usize)));
+  }
+
+  #[test]
+  fn triangle_outside_the_clip_rect_is_skipped() {
+    let fragments = triangle_with_shader(
+      &vertex(-30.5, -30.5),
+      &vertex(-10.5, -30.5),
+      &vertex(-20.5, -5.5),
+      |_, _| Vec3::zeros(),
+      &uniforms(),
+      &[Vec2::new(0.5, 0.5)],
+      &Rect::new(0, 0, SIZE, SIZE),
+    );
+
+    assert!(fragments.is_empty());
+  }
+}
@@ -1,4 +1,4 @@
-use nalgebra_glm::{Vec2, Vec3};
+use nalgebra_glm::{Vec2, Vec3, Vec4};
 use crate::color::Color;
 
 #[derive(Clone, Debug)]
@@ -6,6 +6,8 @@ pub struct Vertex {
   pub position: Vec3,
   pub normal: Vec3,
   pub tex_coords: Vec2,
+  // Object space tangent along +u, with the bitangent's sign in w
+  pub tangent: Vec4,
   pub color: Color,
   pub transformed_position: Vec3,
   pub transformed_normal: Vec3,
@@ -17,27 +19,13 @@ impl Vertex {
       position,
       normal,
       tex_coords,
+      tangent: Vec4::new(1.0, 0.0, 0.0, 1.0),
       color: Color::black(),
       transformed_position: position,
       transformed_normal: normal,
     }
   }
 
-  pub fn new_with_color(position: Vec3, color: Color) -> Self {
-    Vertex {
-      position,
-      normal: Vec3::new(0.0, 0.0, 0.0),
-      tex_coords: Vec2::new(0.0, 0.0),
-      color,
-      transformed_position: Vec3::new(0.0, 0.0, 0.0),
-      transformed_normal: Vec3::new(0.0, 0.0, 0.0),
-    }
-  }
-
-  pub fn set_transformed(&mut self, position: Vec3, normal: Vec3) {
-    self.transformed_position = position;
-    self.transformed_normal = normal;
-  }
 }
 
 impl Default for Vertex {
@@ -46,6 +34,7 @@ impl Default for Vertex {
       position: Vec3::new(0.0, 0.0, 0.0),
       normal: Vec3::new(0.0, 1.0, 0.0),
       tex_coords: Vec2::new(0.0, 0.0),
+      tangent: Vec4::new(1.0, 0.0, 0.0, 1.0),
       color: Color::black(),
       transformed_position: Vec3::new(0.0, 0.0, 0.0),
       transformed_normal: Vec3::new(0.0, 1.0, 0.0),